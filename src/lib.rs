@@ -9,219 +9,7931 @@
 
 // Allow `cargo stylus export-abi` to generate a main function if the "export-abi" feature is enabled.
 #![cfg_attr(not(any(test, feature = "export-abi")), no_main)]
+// The ABI exporter chains one iterator per public method, which outgrows the default limit.
+#![recursion_limit = "512"]
 extern crate alloc;
 
 /// Import items from the SDK. The prelude contains common traits and macros.
-use stylus_sdk::alloy_primitives::{Address, U256};
+use alloy_sol_types::{sol, Eip712Domain, SolCall, SolStruct};
+use stylus_sdk::alloy_primitives::{address, Address, B256, U256};
+#[cfg(not(feature = "no-admin"))]
+use stylus_sdk::alloy_primitives::{I256, U8};
 use stylus_sdk::console;
+use stylus_sdk::crypto::keccak;
 use stylus_sdk::prelude::*;
+use stylus_sdk::stylus_core::calls::context::Call;
 
-sol_storage! {
-    #[entrypoint]
-    pub struct VendingMachine {
-        mapping(address => uint256) cupcake_balances;
-        mapping(address => uint256) cupcake_distribution_times;
+/// Minimum number of seconds between two cupcakes for the same user.
+const COOLDOWN_SECONDS: u64 = 5;
+
+/// Flavor used by the single-flavor ABI, transfers and allowances.
+const DEFAULT_FLAVOR: U256 = U256::ZERO;
+
+/// Length of a daily-limit bucket in seconds.
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Maximum length in bytes of the metadata URI.
+#[cfg(not(feature = "no-admin"))]
+const MAX_METADATA_URI_LENGTH: usize = 256;
+
+/// Maximum length in bytes of the unit label.
+#[cfg(not(feature = "no-admin"))]
+const MAX_UNIT_LABEL_LENGTH: usize = 32;
+
+/// Largest accepted `precision`, matching the usual ERC20 `decimals`.
+#[cfg(not(feature = "no-admin"))]
+const MAX_PRECISION: u8 = 18;
+
+/// EIP-712 signing domain name and version used by `permit`.
+const EIP712_NAME: &str = "VendingMachine";
+const EIP712_VERSION: &str = "1";
+
+/// The `ecrecover` precompile.
+const ECRECOVER: Address = address!("0000000000000000000000000000000000000001");
+
+/// Basis-point denominator for fees and supply shares.
+const FEE_DENOMINATOR: u64 = 10_000;
+
+/// Contract version reported by `version`, taken from the crate version at build time.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Reason codes returned by `try_give`. Zero means the cupcake was given.
+const REASON_OK: u8 = 0;
+/// The user is still cooling down.
+const REASON_COOLDOWN: u8 = 1;
+/// The machine is paused.
+const REASON_PAUSED: u8 = 2;
+/// The grant would go past the supply cap.
+const REASON_SOLD_OUT: u8 = 3;
+/// The user is blacklisted.
+const REASON_BLACKLISTED: u8 = 4;
+/// The user is a contract and contract recipients are blocked.
+const REASON_CONTRACT: u8 = 5;
+/// The account hasn't reached the minimum age yet.
+const REASON_TOO_NEW: u8 = 6;
+/// The user has hit today's claim limit.
+const REASON_DAILY_LIMIT: u8 = 7;
+/// The user is frozen.
+const REASON_FROZEN: u8 = 8;
+/// The user has received their lifetime quota.
+const REASON_LIFETIME_CAP: u8 = 9;
+/// The user hasn't staked `required_stake`.
+const REASON_STAKE_TOO_LOW: u8 = 10;
+/// The claim tripped the circuit breaker, which paused the machine.
+const REASON_CIRCUIT_BREAKER: u8 = 11;
+/// The recipient is the zero address and the fallback to the caller is off.
+const REASON_ZERO_RECIPIENT: u8 = 12;
+/// This block has already seen `max_claims_per_block` claims.
+const REASON_BLOCK_LIMIT: u8 = 13;
+
+/// Cost buckets returned by `claim_complexity`.
+/// The claim writes the user's distribution time for the first time.
+const COMPLEXITY_FIRST_CLAIM: u8 = 0;
+/// The claim only updates slots the user already has.
+const COMPLEXITY_REPEAT_CLAIM: u8 = 1;
+/// The claim also mints an auto-compounded streak bonus.
+const COMPLEXITY_BONUS_CLAIM: u8 = 2;
+
+/// Rules reported by `explain_cooldown` as deciding a user's cooldown.
+/// The base cooldown applies unchanged.
+const COOLDOWN_RULE_GLOBAL: u8 = 0;
+/// A per-user override from `set_user_cooldowns`.
+const COOLDOWN_RULE_OVERRIDE: u8 = 1;
+/// Everyone is exempt during the launch grace period.
+const COOLDOWN_RULE_EXEMPT: u8 = 2;
+/// The owner waived the cooldown for everyone.
+const COOLDOWN_RULE_WAIVED: u8 = 3;
+/// The base cooldown grown by the user's balance.
+const COOLDOWN_RULE_BALANCE_SCALED: u8 = 4;
+
+// Units the base cooldown can be expressed in, see `get_cooldown_display`.
+/// The base cooldown counts seconds.
+const COOLDOWN_UNIT_SECONDS: u8 = 0;
+/// The base cooldown counts minutes.
+const COOLDOWN_UNIT_MINUTES: u8 = 1;
+/// The base cooldown counts hours.
+const COOLDOWN_UNIT_HOURS: u8 = 2;
+
+// Reason codes carried by `BalanceChanged`.
+/// Minted by a claim, bonus, keeper reward or purchase.
+const BALANCE_REASON_GRANT: u8 = 0;
+/// Received in a transfer.
+const BALANCE_REASON_TRANSFER_IN: u8 = 1;
+/// Sent in a transfer.
+const BALANCE_REASON_TRANSFER_OUT: u8 = 2;
+/// Eaten, or burned as a claim or transfer fee.
+const BALANCE_REASON_BURN: u8 = 3;
+/// Changed by the owner: adjustments, imports and confiscations.
+#[cfg(not(feature = "no-admin"))]
+const BALANCE_REASON_ADMIN: u8 = 4;
+/// Decay written to storage.
+const BALANCE_REASON_DECAY: u8 = 5;
+
+sol! {
+    /// Minimal ERC20 surface used to pay out and recover tokens.
+    interface IERC20 {
+        function transfer(address to, uint256 amount) external returns (bool);
+        function balanceOf(address account) external view returns (uint256);
+    }
+
+    /// Balance getter of the original vending machine, read by `import_from_legacy`.
+    interface ILegacyVendingMachine {
+        function getCupcakeBalanceFor(address userAddress) external view returns (uint256);
+    }
+
+    /// ERC721-style acceptance hook called on contract recipients of a mint while
+    /// `safe_mint` is on. `tokenId` carries the flavor.
+    interface IERC721Receiver {
+        function onERC721Received(address operator, address from, uint256 tokenId, bytes data) external returns (bytes4);
+    }
+
+    /// ERC677 hook called on the recipient of `transfer_and_call`.
+    interface IERC677Receiver {
+        function onTokenTransfer(address from, uint256 amount, bytes data) external;
+    }
+
+    /// EIP-2612 permit message.
+    struct Permit {
+        address owner;
+        address spender;
+        uint256 value;
+        uint256 nonce;
+        uint256 deadline;
+    }
+
+    /// Every configured bound, as returned by `get_limits`. Zero means unbounded.
+    #[derive(AbiType)]
+    struct Limits {
+        uint256 maxSupply;
+        uint256 dailyLimit;
+        uint256 lifetimeCap;
+        uint256 rateLimit;
+        uint256 rateWindow;
+        uint256 maxClaimsPerBlock;
+        uint256 maxBatchSize;
+    }
+
+    /// `init` was called on a contract that already has an owner.
+    error AlreadyInitialized();
+    /// The caller is not allowed to perform an owner-only action.
+    error NotOwner(address caller);
+    /// The recipient is a contract and `block_contracts` is enabled.
+    error ContractRecipient(address recipient);
+    /// The sender does not hold enough cupcakes for the transfer.
+    error InsufficientBalance(address from, uint256 balance, uint256 needed);
+    /// Batch inputs that must be paired have different lengths.
+    error LengthMismatch();
+    /// An ERC20 `transfer` call on `token` reverted or returned false.
+    error TokenTransferFailed(address token);
+    /// The recipient's `onTokenTransfer` hook reverted.
+    error TransferCallbackFailed(address receiver);
+    /// Owner config changes are throttled by `admin_cooldown`.
+    error ConfigChangeTooSoon(uint256 nextAllowed);
+    /// The flavor's own supply cap has been reached.
+    error FlavorSoldOut(uint256 flavorId, uint256 maxSupply);
+    /// Historical lookups can't ask about blocks that haven't happened yet.
+    error FutureBlock(uint256 block, uint256 current);
+    /// Cupcakes can't be given to the zero address unless the sender fallback is on.
+    error ZeroRecipient();
+    /// Tier thresholds must be strictly increasing and fit the `uint8` tier index.
+    error InvalidTierThresholds();
+    /// The user has already received `cap` cupcakes over their lifetime.
+    error LifetimeCapReached(address user, uint256 cap);
+    /// The user's ETH stake is below `required_stake`.
+    error StakeTooLow(address user, uint256 stake, uint256 required);
+    /// The user tried to unstake more than they have staked.
+    error InsufficientStake(address user, uint256 stake, uint256 needed);
+    /// Sending ETH back to the user failed.
+    error EthTransferFailed(address to, uint256 amount);
+    /// This block already holds `max_claims_per_block` claims.
+    error BlockClaimLimit(uint256 blockNumber, uint256 max);
+    /// Reading `user`'s balance from the legacy contract failed.
+    error LegacyReadFailed(address legacy, address user);
+    /// `unit` is not one of the `COOLDOWN_UNIT_*` values.
+    error InvalidCooldownUnit(uint8 unit);
+    /// Only `available` reward tokens are left once future claim payouts are set aside.
+    error RewardTokenReserved(address token, uint256 available);
+    /// `user` tried to move cupcakes to themselves while `reject_self_transfers` is on.
+    error SelfTransfer(address user);
+    /// The owner was last active at `lastSeen`, more than `dead_man_timeout` ago, so admin
+    /// methods are permanently disabled.
+    error AdminExpired(uint256 lastSeen);
+    /// `user` has no scheduled claim to execute.
+    error NoScheduledClaim(address user);
+    /// `user`'s scheduled claim can't be executed before `eligibleAt`.
+    error ScheduledClaimTooEarly(address user, uint256 eligibleAt);
+    /// The unit label is longer than the allowed maximum.
+    error UnitLabelTooLong(uint256 length, uint256 max);
+    /// `precision` is above the allowed maximum.
+    error InvalidPrecision(uint8 precision, uint8 max);
+    /// `freeze_config` was called, so configuration can no longer change.
+    error ConfigFrozen();
+    /// `buy_cupcake` was called while no price is set.
+    error NotForSale();
+    /// `sent` wei doesn't cover the `price` of a purchase.
+    error Underpaid(uint256 sent, uint256 price);
+    /// The contract at `to` didn't accept a mint through `onERC721Received`.
+    error UnsafeRecipient(address to);
+    /// The user already received `limit` cupcakes in the current day.
+    error DailyLimitReached(address user, uint256 limit);
+    /// The spender's allowance is too small for the requested amount.
+    error InsufficientAllowance(address spender, uint256 allowance, uint256 needed);
+    /// An arithmetic result does not fit in a uint256.
+    error Overflow();
+    /// The recipient was first seen too recently to claim; retry at `eligibleAt`.
+    error AccountTooNew(address user, uint256 eligibleAt);
+    /// The account is blacklisted and cannot send or receive cupcakes.
+    error Blacklisted(address user);
+    /// The action requires the account to be blacklisted first.
+    error NotBlacklisted(address user);
+    /// The account is frozen and cannot claim, send or receive cupcakes for now.
+    error AccountFrozen(address user);
+    /// A signed message was submitted after its deadline.
+    error SignatureExpired(uint256 deadline);
+    /// The signature does not recover to the expected signer.
+    error InvalidSignature();
+    /// A voucher was redeemed after its deadline.
+    error VoucherExpired(uint256 deadline);
+    /// The contract is paused.
+    error ContractPaused();
+    /// The metadata URI exceeds the maximum length.
+    error MetadataUriTooLong(uint256 length, uint256 max);
+    /// The caller's streak hasn't reached the bonus threshold.
+    error StreakTooShort(uint256 streak, uint256 threshold);
+    /// Distributing would take the machine past its supply cap.
+    error SoldOut(uint256 maxSupply);
+    /// A strict batch distribution hit a user who can't receive a cupcake.
+    error IneligibleRecipient(address user);
+    /// The keeper reward can't be larger than the grant it is paid for.
+    error KeeperRewardTooHigh(uint256 reward, uint256 max);
+    /// The user is still cooling down.
+    error CooldownActive(address user, uint256 remaining);
+    /// The user has a streak that can still be extended.
+    error StreakActive(address user, uint256 streak);
+    /// The merkle proof doesn't lead to the current allowlist root.
+    error InvalidProof();
+    /// The user already claimed against the current allowlist root.
+    error AlreadyClaimed(address user, bytes32 root);
+    /// Transfer fees can't exceed 100%.
+    error FeeTooHigh(uint256 feeBps, uint256 max);
+    /// A batch input is longer than `max_batch_size`.
+    error BatchTooLarge(uint256 length, uint256 max);
+    /// There is no proposed cooldown to execute.
+    error NoPendingCooldown();
+    /// The proposed cooldown can't be executed before `eta`.
+    error TimelockNotElapsed(uint256 eta);
+    /// `precision` can't change once `totalSupply` cupcakes exist.
+    error PrecisionLocked(uint256 totalSupply);
+
+    /// Emitted on every successful claim. `claimNumber` counts the user's claims from 1.
+    event UserClaim(address indexed user, uint256 indexed claimNumber, uint256 timestamp);
+    /// Emitted when cupcakes move between holders.
+    event Transfer(address indexed from, address indexed to, uint256 value);
+    /// Emitted when an allowance changes. `value` is the resulting allowance, not the delta.
+    event Approval(address indexed owner, address indexed spender, uint256 value);
+    /// Snapshot of the contract's aggregates, pushed on demand by `emit_metrics`.
+    event MetricsSnapshot(uint256 totalDistributed, uint256 totalSupply, uint256 holders, uint256 timestamp);
+    /// Emitted when a claim would have pushed grants past `rateLimit` within `rateWindow`
+    /// seconds and the contract paused itself.
+    event CircuitBreakerTripped(uint256 rateLimit, uint256 rateWindow, uint256 timestamp);
+    /// Emitted when the owner overrides a user's default-flavor distribution time.
+    event DistributionTimeSet(address indexed user, uint256 previous, uint256 timestamp);
+    /// Emitted once by `set_blacklisted_many` for the whole batch.
+    event BatchBlacklistUpdated(uint256 count, bool blocked);
+    /// Emitted for every change to a balance of any flavor while `balance_events` is on.
+    /// `reason` is one of the `BALANCE_REASON_*` codes.
+    event BalanceChanged(address indexed user, uint256 indexed flavorId, uint256 oldBalance, uint256 newBalance, uint8 reason);
+}
+
+#[derive(SolidityError)]
+pub enum VendingMachineError {
+    AlreadyInitialized(AlreadyInitialized),
+    NotOwner(NotOwner),
+    ContractRecipient(ContractRecipient),
+    InsufficientBalance(InsufficientBalance),
+    LengthMismatch(LengthMismatch),
+    TokenTransferFailed(TokenTransferFailed),
+    DailyLimitReached(DailyLimitReached),
+    InsufficientAllowance(InsufficientAllowance),
+    Overflow(Overflow),
+    AccountTooNew(AccountTooNew),
+    Blacklisted(Blacklisted),
+    NotBlacklisted(NotBlacklisted),
+    AccountFrozen(AccountFrozen),
+    SignatureExpired(SignatureExpired),
+    InvalidSignature(InvalidSignature),
+    VoucherExpired(VoucherExpired),
+    ContractPaused(ContractPaused),
+    MetadataUriTooLong(MetadataUriTooLong),
+    StreakTooShort(StreakTooShort),
+    SoldOut(SoldOut),
+    IneligibleRecipient(IneligibleRecipient),
+    KeeperRewardTooHigh(KeeperRewardTooHigh),
+    CooldownActive(CooldownActive),
+    StreakActive(StreakActive),
+    InvalidProof(InvalidProof),
+    AlreadyClaimed(AlreadyClaimed),
+    FeeTooHigh(FeeTooHigh),
+    BatchTooLarge(BatchTooLarge),
+    NoPendingCooldown(NoPendingCooldown),
+    TransferCallbackFailed(TransferCallbackFailed),
+    ConfigChangeTooSoon(ConfigChangeTooSoon),
+    FlavorSoldOut(FlavorSoldOut),
+    FutureBlock(FutureBlock),
+    ZeroRecipient(ZeroRecipient),
+    InvalidTierThresholds(InvalidTierThresholds),
+    LifetimeCapReached(LifetimeCapReached),
+    StakeTooLow(StakeTooLow),
+    InsufficientStake(InsufficientStake),
+    EthTransferFailed(EthTransferFailed),
+    BlockClaimLimit(BlockClaimLimit),
+    LegacyReadFailed(LegacyReadFailed),
+    InvalidCooldownUnit(InvalidCooldownUnit),
+    RewardTokenReserved(RewardTokenReserved),
+    SelfTransfer(SelfTransfer),
+    AdminExpired(AdminExpired),
+    NoScheduledClaim(NoScheduledClaim),
+    ScheduledClaimTooEarly(ScheduledClaimTooEarly),
+    UnitLabelTooLong(UnitLabelTooLong),
+    InvalidPrecision(InvalidPrecision),
+    ConfigFrozen(ConfigFrozen),
+    NotForSale(NotForSale),
+    Underpaid(Underpaid),
+    UnsafeRecipient(UnsafeRecipient),
+    TimelockNotElapsed(TimelockNotElapsed),
+    PrecisionLocked(PrecisionLocked),
+}
+
+// `#[derive(AbiType)]` doesn't register structs for `export-abi`, so `Limits` describes
+// itself to the exporter by hand. Keep the string in sync with the `sol!` definition.
+#[cfg(feature = "export-abi")]
+impl stylus_sdk::abi::export::internal::InnerTypes for Limits {
+    fn inner_types() -> Vec<stylus_sdk::abi::export::internal::InnerType> {
+        vec![stylus_sdk::abi::export::internal::InnerType {
+            name: "struct Limits { uint256 maxSupply; uint256 dailyLimit; uint256 lifetimeCap; \
+                   uint256 rateLimit; uint256 rateWindow; uint256 maxClaimsPerBlock; \
+                   uint256 maxBatchSize; }"
+                .into(),
+            id: core::any::TypeId::of::<Limits>(),
+        }]
+    }
+}
+
+/// Declares the contract storage with `$owner` spliced in as the owner slot, so the
+/// `no-admin` build can drop the field.
+macro_rules! vending_machine_storage {
+    ($($owner:tt)*) => {
+        sol_storage! {
+            #[entrypoint]
+            pub struct VendingMachine {
+                mapping(uint256 => mapping(address => uint256)) cupcake_balances;
+                mapping(uint256 => mapping(address => uint256)) cupcake_distribution_times;
+                $($owner)*
+                bool block_contracts;
+                mapping(address => uint256) claim_counts;
+                address reward_token;
+                uint256 reward_amount;
+                uint256 daily_limit;
+                mapping(address => uint256) claim_days;
+                mapping(address => uint256) daily_claims;
+                uint256 grant_amount;
+                uint256 cooldown_waived_until;
+                mapping(address => mapping(address => uint256)) allowances;
+                uint256 min_account_age;
+                mapping(address => uint256) first_seen;
+                mapping(address => bool) blacklisted;
+                uint256 total_supply;
+                uint256 start_time;
+                uint256 grace_period;
+                mapping(address => uint256) nonces;
+                uint256 cooldown_step;
+                uint256 max_cooldown;
+                bool paused;
+                string metadata_uri;
+                mapping(address => uint256) streaks;
+                uint256 streak_window;
+                uint256 streak_bonus_threshold;
+                uint256 streak_bonus_amount;
+                mapping(address => bool) has_cooldown_override;
+                mapping(address => uint256) cooldown_overrides;
+                uint256 max_supply;
+                uint256 total_distributed;
+                uint256 keeper_reward;
+                mapping(address => bool) frozen;
+                bytes32 merkle_root;
+                mapping(bytes32 => mapping(address => bool)) merkle_claims;
+                uint256 transfer_fee_bps;
+                uint256 min_transfer_fee;
+                bool fee_round_half_up;
+                uint256 max_batch_size;
+                mapping(address => uint256) holdings;
+                uint256 holder_count;
+                mapping(address => bool) auto_compound;
+                bool has_base_cooldown;
+                uint256 base_cooldown;
+                uint256 timelock_delay;
+                uint256 pending_cooldown;
+                uint256 pending_cooldown_eta;
+                uint256 admin_cooldown;
+                uint256 last_config_change;
+                mapping(uint256 => uint256) flavor_total_supply;
+                mapping(uint256 => uint256) flavor_max_supply;
+                bool decay_enabled;
+                uint256 decay_per_second;
+                uint256 decay_since;
+                mapping(address => uint256) last_activity;
+                uint256 claim_fee_bps;
+                uint256[] supply_checkpoint_blocks;
+                uint256[] supply_checkpoint_values;
+                uint256 rate_limit;
+                uint256 rate_window;
+                uint256[] recent_grants;
+                uint256 recent_grants_next;
+                uint256[] flavor_ids;
+                mapping(uint256 => bool) flavor_registered;
+                bool zero_address_fallback;
+                mapping(address => bytes32) history_roots;
+                uint256[] tier_thresholds;
+                uint256 lifetime_cap;
+                mapping(address => uint256) lifetime_received;
+                bool burns_restore_quota;
+                bytes32 cached_domain_separator;
+                uint256 cached_chain_id;
+                uint256 required_stake;
+                mapping(address => uint256) stakes;
+                uint256 max_claims_per_block;
+                uint256 claims_block;
+                uint256 claims_in_block;
+                uint256 halving_interval;
+                uint256 min_grant_amount;
+                bool anti_circumvention;
+                uint8 cooldown_unit;
+                uint8 pending_cooldown_unit;
+                uint256 first_claim_bonus;
+                uint256 display_cap;
+                uint256 jitter_range;
+                uint256 admin_action_count;
+                bool reject_self_transfers;
+                address[] holder_list;
+                mapping(address => bool) in_holder_list;
+                uint256 owner_last_seen;
+                uint256 dead_man_timeout;
+                mapping(address => uint256) scheduled_claims;
+                string unit_label;
+                uint8 precision;
+                bool config_frozen;
+                bool freeze_locks_pause;
+                mapping(address => uint256) burned;
+                uint256 total_burned;
+                uint256 cupcake_price;
+                bool strict_payment;
+                uint256 sale_proceeds;
+                bool safe_mint;
+                bool balance_events;
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "no-admin"))]
+vending_machine_storage!(address owner;);
+
+#[cfg(feature = "no-admin")]
+vending_machine_storage!();
+
+/// Expands to the contract's `#[public]` impl. Owner-gated methods go in the `admin`
+/// section, which the `no-admin` feature leaves out; `#[public]` routes every method it
+/// is given, so a `#[cfg]` on an individual method isn't enough.
+///
+/// The attribute is passed in rather than written here so the code `#[public]` generates
+/// resolves names at the invocation site.
+macro_rules! public_impl {
+    ($pound:tt $public:tt impl $name:ident { admin { $($admin:tt)* } $($core:tt)* }) => {
+        #[cfg(not(feature = "no-admin"))]
+        $pound $public
+        impl $name {
+            $($admin)*
+            $($core)*
+        }
+
+        #[cfg(feature = "no-admin")]
+        $pound $public
+        impl $name {
+            $($core)*
+        }
+    };
+}
+
+public_impl! {
+    #[public]
+    impl VendingMachine {
+        admin {
+            /// Sets the caller as the owner. Must be called once right after deployment.
+            pub fn init(&mut self) -> Result<(), Vec<u8>> {
+                if !self.owner.get().is_zero() {
+                    return Err(
+                        VendingMachineError::AlreadyInitialized(AlreadyInitialized {}).into(),
+                    );
+                }
+                let sender = self.vm().msg_sender();
+                self.owner.set(sender);
+                self.start_time.set(U256::from(self.vm().block_timestamp()));
+                self.owner_last_seen
+                    .set(U256::from(self.vm().block_timestamp()));
+                self.cache_domain_separator();
+                Ok(())
+            }
+
+            /// Recovery path for a deployment where `init` was never called: makes the
+            /// caller the owner exactly as `init` does, and fails the same way with
+            /// `AlreadyInitialized` once anyone owns the contract.
+            pub fn claim_ownership_if_unset(&mut self) -> Result<(), Vec<u8>> {
+                self.init()
+            }
+
+            /// Skips the cooldown for the first `seconds` after `start_time` so everyone can
+            /// grab an initial cupcake.
+            pub fn set_grace_period(&mut self, seconds: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.grace_period.set(seconds);
+                Ok(())
+            }
+
+            pub fn owner(&self) -> Address {
+                self.owner.get()
+            }
+
+            /// Whether the caller is the owner, so a UI can gate its admin panel in one call.
+            pub fn am_i_owner(&self) -> bool {
+                self.vm().msg_sender() == self.owner.get()
+            }
+
+            /// Dead-man switch: once the owner has gone `timeout` seconds without calling an
+            /// admin method, every admin method reverts with `AdminExpired` for good and the
+            /// configuration is frozen as it stands. Zero disables the switch. `unpause`,
+            /// `withdraw_proceeds` and `recover_erc20` stay open to the owner so a pause or
+            /// funds can't be stranded, but calling them doesn't revive anything else.
+            pub fn set_dead_man_timeout(&mut self, timeout: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.dead_man_timeout.set(timeout);
+                Ok(())
+            }
+
+            /// Whether admin methods still work, i.e. the dead-man switch hasn't fired.
+            pub fn is_admin_active(&self) -> bool {
+                let timeout = self.dead_man_timeout.get();
+                timeout.is_zero()
+                    || U256::from(self.vm().block_timestamp())
+                        <= self.owner_last_seen.get().saturating_add(timeout)
+            }
+
+            /// Rejects recipients that have code deployed at their address.
+            ///
+            /// This only deters bots: a contract calling from its constructor has no code yet
+            /// and will still pass the check.
+            pub fn set_block_contracts(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.block_contracts.set(enabled);
+                Ok(())
+            }
+
+            /// Pays `amount` of the ERC20 `token` to every successful claimer.
+            /// Setting `token` to the zero address disables the payout.
+            pub fn set_reward_token(
+                &mut self,
+                token: Address,
+                amount: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.reward_token.set(token);
+                self.reward_amount.set(amount);
+                Ok(())
+            }
+
+            /// Moves `amount` of the contract's leftover reward tokens to `to`.
+            pub fn sweep_reward_tokens(
+                &mut self,
+                to: Address,
+                amount: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                let token = self.reward_token.get();
+                if token.is_zero() {
+                    return Err(
+                        VendingMachineError::TokenTransferFailed(TokenTransferFailed { token })
+                            .into(),
+                    );
+                }
+                self.erc20_transfer(token, to, amount)
+            }
+
+            /// Moves `amount` of an ERC20 sent to the contract by mistake to `to`. For the
+            /// reward token, only the balance above what the remaining claims until sell-out
+            /// would pay out can be recovered, so with no supply cap none of it can;
+            /// `sweep_reward_tokens` is the unrestricted path for that.
+            pub fn recover_erc20(
+                &mut self,
+                token: Address,
+                to: Address,
+                amount: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_recovery()?;
+                if token == self.reward_token.get() {
+                    let reserved = self
+                        .reward_amount
+                        .get()
+                        .saturating_mul(self.claims_until_sold_out()?);
+                    let balance = self.erc20_balance_of(token, self.vm().contract_address())?;
+                    let available = balance.saturating_sub(reserved);
+                    if amount > available {
+                        return Err(VendingMachineError::RewardTokenReserved(
+                            RewardTokenReserved { token, available },
+                        )
+                        .into());
+                    }
+                }
+                self.erc20_transfer(token, to, amount)
+            }
+
+            /// Caps how many cupcakes a user can receive per day, independently of the
+            /// cooldown. Days are fixed UTC buckets of `block_timestamp / 86400`. Zero disables the cap.
+            pub fn set_daily_limit(&mut self, limit: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.daily_limit.set(limit);
+                Ok(())
+            }
+
+            /// Sets how many cupcakes a successful claim grants. Zero restores the default of one.
+            pub fn set_grant_amount(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.grant_amount.set(amount);
+                Ok(())
+            }
+
+            /// Spreads cooldowns that would otherwise end together by adding a fixed per-user
+            /// offset of `keccak(user) % range` seconds. Zero disables the jitter.
+            pub fn set_jitter_range(&mut self, range: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.jitter_range.set(range);
+                Ok(())
+            }
+
+            /// Caps what `get_cupcake_balance_for` reports, in base units, for "99+" style
+            /// displays. Balances themselves are unaffected. Zero disables the cap.
+            pub fn set_display_cap(&mut self, cap: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.display_cap.set(cap);
+                Ok(())
+            }
+
+            /// Extra cupcakes added to a user's very first successful claim.
+            pub fn set_first_claim_bonus(&mut self, bonus: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.first_claim_bonus.set(bonus);
+                Ok(())
+            }
+
+            /// Pays whoever calls `give_cupcakes_to` `reward` cupcakes per grant they trigger.
+            /// The reward may not exceed the current grant amount once both are scaled by
+            /// `10^precision`.
+            pub fn set_keeper_reward(&mut self, reward: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                let max = self.current_grant_amount();
+                let scaled = self.to_base_units(reward);
+                if scaled > max {
+                    return Err(
+                        VendingMachineError::KeeperRewardTooHigh(KeeperRewardTooHigh {
+                            reward: scaled,
+                            max,
+                        })
+                        .into(),
+                    );
+                }
+                self.keeper_reward.set(reward);
+                Ok(())
+            }
+
+            /// Waives the cooldown for everyone until `timestamp`, e.g. during a launch event.
+            pub fn set_cooldown_waived_until(&mut self, timestamp: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.cooldown_waived_until.set(timestamp);
+                Ok(())
+            }
+
+            /// Requires recipients to have been first seen at least `seconds` ago before claiming.
+            pub fn set_min_account_age(&mut self, seconds: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.min_account_age.set(seconds);
+                Ok(())
+            }
+
+            /// Blocks or unblocks an account from claiming and transferring cupcakes.
+            pub fn set_blacklisted(&mut self, user: Address, blocked: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                self.blacklisted.setter(user).set(blocked);
+                Ok(())
+            }
+
+            /// Applies `set_blacklisted` to every address in `users`, with a single event for
+            /// the batch.
+            pub fn set_blacklisted_many(
+                &mut self,
+                users: Vec<Address>,
+                blocked: bool,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                self.check_batch_size(users.len())?;
+                for user in &users {
+                    self.blacklisted.setter(*user).set(blocked);
+                }
+                log(
+                    self.vm(),
+                    BatchBlacklistUpdated {
+                        count: U256::from(users.len()),
+                        blocked,
+                    },
+                );
+                Ok(())
+            }
+
+            /// Freezes or unfreezes an account. Unlike the blacklist, a freeze is meant to be
+            /// temporary: the balance stays put and can't be confiscated.
+            pub fn set_frozen(&mut self, user: Address, frozen: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                self.frozen.setter(user).set(frozen);
+                Ok(())
+            }
+
+            /// Sets the root of the merkle allowlist used by `claim_with_proof`. Every new
+            /// root starts with a clean slate of claims.
+            pub fn set_merkle_root(&mut self, root: B256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.merkle_root.set(root);
+                Ok(())
+            }
+
+            /// Burns the entire balance of a blacklisted account, across every flavor, and
+            /// returns the total amount burned.
+            pub fn confiscate(&mut self, user: Address) -> Result<U256, Vec<u8>> {
+                self.only_owner_action()?;
+                if !self.blacklisted.get(user) {
+                    return Err(VendingMachineError::NotBlacklisted(NotBlacklisted { user }).into());
+                }
+
+                // The default flavor isn't registered until someone claims it.
+                let mut flavors = vec![DEFAULT_FLAVOR];
+                flavors.extend(
+                    (0..self.flavor_ids.len())
+                        .filter_map(|i| self.flavor_ids.get(i))
+                        .filter(|flavor| *flavor != DEFAULT_FLAVOR),
+                );
+                let mut amount = U256::ZERO;
+                for flavor in flavors {
+                    self.materialize_decay(user, flavor);
+                    let balance = self.flavor_balance(user, flavor);
+                    if !balance.is_zero() {
+                        self.burn(user, flavor, balance, BALANCE_REASON_ADMIN)?;
+                        amount += balance;
+                    }
+                }
+                log(
+                    self.vm(),
+                    Transfer {
+                        from: user,
+                        to: Address::ZERO,
+                        value: amount,
+                    },
+                );
+                Ok(amount)
+            }
+
+            /// Seeds default-flavor balances from an old deployment at `legacy` by reading its
+            /// `getCupcakeBalanceFor` for each of `users`. Users who already hold cupcakes
+            /// here are skipped so a list can't be imported twice. Returns the total minted.
+            pub fn import_from_legacy(
+                &mut self,
+                legacy: Address,
+                users: Vec<Address>,
+            ) -> Result<U256, Vec<u8>> {
+                self.only_owner_action()?;
+                self.check_batch_size(users.len())?;
+                let mut imported = U256::ZERO;
+                for user in users {
+                    if !self.flavor_balance(user, DEFAULT_FLAVOR).is_zero() {
+                        continue;
+                    }
+                    let amount = self.legacy_balance(legacy, user)?;
+                    if amount.is_zero() {
+                        continue;
+                    }
+                    self.mint(user, DEFAULT_FLAVOR, amount, BALANCE_REASON_ADMIN)?;
+                    imported = imported
+                        .checked_add(amount)
+                        .ok_or(VendingMachineError::Overflow(Overflow {}))?;
+                }
+                Ok(imported)
+            }
+
+            /// Credits or debits `user` by a signed `delta`, for support tooling. Debits can't
+            /// take the balance below zero. Returns the new balance.
+            pub fn adjust_balance(&mut self, user: Address, delta: I256) -> Result<U256, Vec<u8>> {
+                self.only_owner_action()?;
+                let amount = delta.unsigned_abs();
+                if delta.is_negative() {
+                    self.burn(user, DEFAULT_FLAVOR, amount, BALANCE_REASON_ADMIN)?;
+                    log(
+                        self.vm(),
+                        Transfer {
+                            from: user,
+                            to: Address::ZERO,
+                            value: amount,
+                        },
+                    );
+                } else {
+                    self.mint(user, DEFAULT_FLAVOR, amount, BALANCE_REASON_ADMIN)?;
+                }
+                Ok(self.flavor_balance(user, DEFAULT_FLAVOR))
+            }
+
+            /// Burns a fee of `fee_bps` basis points on every transfer, whichever method moves
+            /// the cupcakes. The fee is rounded down, or half-up if `round_half_up` is set, and
+            /// never drops below `min_fee` base units so dust transfers can't dodge it. A zero
+            /// `fee_bps` disables the fee.
+            pub fn set_transfer_fee(
+                &mut self,
+                fee_bps: U256,
+                min_fee: U256,
+                round_half_up: bool,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                let max = U256::from(FEE_DENOMINATOR);
+                if fee_bps > max {
+                    return Err(VendingMachineError::FeeTooHigh(FeeTooHigh {
+                        feeBps: fee_bps,
+                        max,
+                    })
+                    .into());
+                }
+                self.transfer_fee_bps.set(fee_bps);
+                self.min_transfer_fee.set(min_fee);
+                self.fee_round_half_up.set(round_half_up);
+                Ok(())
+            }
+
+            /// Burns `fee_bps` basis points of the claimant's existing balance of the claimed
+            /// flavor on every claim, rounded down, before the grant is added.
+            pub fn set_claim_fee(&mut self, fee_bps: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                let max = U256::from(FEE_DENOMINATOR);
+                if fee_bps > max {
+                    return Err(VendingMachineError::FeeTooHigh(FeeTooHigh {
+                        feeBps: fee_bps,
+                        max,
+                    })
+                    .into());
+                }
+                self.claim_fee_bps.set(fee_bps);
+                Ok(())
+            }
+
+            /// Permanently locks the configuration: every config setter, per-user cooldown
+            /// overrides, `set_distribution_time` and executing a proposed cooldown revert
+            /// with `ConfigFrozen` from now on. Moderation such as
+            /// the blacklist stays available, as does pausing unless `set_freeze_locks_pause`
+            /// was enabled first.
+            pub fn freeze_config(&mut self) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                self.ensure_config_not_frozen()?;
+                self.config_frozen.set(true);
+                Ok(())
+            }
+
+            /// Whether `freeze_config` also takes away `pause`. `unpause` always stays
+            /// available so a tripped circuit breaker can't leave the contract stuck.
+            pub fn set_freeze_locks_pause(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.freeze_locks_pause.set(enabled);
+                Ok(())
+            }
+
+            pub fn is_config_frozen(&self) -> bool {
+                self.config_frozen.get()
+            }
+
+            /// Halts claims, transfers and approvals. Owner configuration stays available.
+            pub fn pause(&mut self) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                if self.freeze_locks_pause.get() {
+                    self.ensure_config_not_frozen()?;
+                }
+                self.paused.set(true);
+                Ok(())
+            }
+
+            /// Unpausing also forgets recent grants, so a tripped circuit breaker doesn't trip
+            /// again on the very next claim.
+            pub fn unpause(&mut self) -> Result<(), Vec<u8>> {
+                self.only_owner_recovery()?;
+                self.paused.set(false);
+                self.reset_recent_grants();
+                Ok(())
+            }
+
+            /// Replaces the balance tiers: a balance of at least `thresholds[i]` reaches tier
+            /// `i + 1`, and anything below the first threshold is tier 0.
+            pub fn set_tier_thresholds(&mut self, thresholds: Vec<U256>) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                let ascending = thresholds.windows(2).all(|pair| pair[0] < pair[1]);
+                if !ascending || thresholds.len() > u8::MAX as usize {
+                    return Err(VendingMachineError::InvalidTierThresholds(
+                        InvalidTierThresholds {},
+                    )
+                    .into());
+                }
+                self.tier_thresholds.truncate(0);
+                for threshold in thresholds {
+                    self.tier_thresholds.push(threshold);
+                }
+                Ok(())
+            }
+
+            /// Caps how many cupcakes any one user can receive from claims over their lifetime,
+            /// across all flavors, in base units. Zero means unbounded.
+            pub fn set_lifetime_cap(&mut self, cap: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.lifetime_cap.set(cap);
+                Ok(())
+            }
+
+            /// Halves the grant every `interval` seconds since `start_time`, never going below
+            /// `min_grant` (or the base grant, if that is smaller). A zero `interval` disables
+            /// halving.
+            pub fn set_halving(&mut self, interval: U256, min_grant: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.halving_interval.set(interval);
+                self.min_grant_amount.set(min_grant);
+                Ok(())
+            }
+
+            /// When enabled, transfers whose sender and recipient are the same account revert
+            /// with `SelfTransfer` instead of succeeding as a no-op.
+            pub fn set_reject_self_transfers(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.reject_self_transfers.set(enabled);
+                Ok(())
+            }
+
+            /// When enabled, transfer recipients inherit the sender's default-flavor cooldown
+            /// if it is more recent than their own, so cupcakes can't be passed along to an
+            /// account that then claims straight away.
+            pub fn set_anti_circumvention(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.anti_circumvention.set(enabled);
+                Ok(())
+            }
+
+            /// Caps how many claims, across all users, can land in one block. Zero means
+            /// unbounded.
+            pub fn set_max_claims_per_block(&mut self, max: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.max_claims_per_block.set(max);
+                Ok(())
+            }
+
+            /// Requires claimants to have at least `amount` wei staked through `stake`. Zero
+            /// turns the requirement off.
+            pub fn set_required_stake(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.required_stake.set(amount);
+                Ok(())
+            }
+
+            /// Turns the `BalanceChanged` stream on or off. It covers every flavor.
+            pub fn set_balance_events(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.balance_events.set(enabled);
+                Ok(())
+            }
+
+            /// When enabled, every mint to an address with code first calls its
+            /// `onERC721Received` and reverts with `UnsafeRecipient` unless it returns the
+            /// function's selector, so cupcakes can't end up in contracts unaware of them.
+            pub fn set_safe_mint(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.safe_mint.set(enabled);
+                Ok(())
+            }
+
+            /// Price in wei of one `buy_cupcake` purchase. Zero stops sales.
+            pub fn set_cupcake_price(&mut self, price: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.cupcake_price.set(price);
+                Ok(())
+            }
+
+            /// Whether an underpaid `buy_cupcake` reverts with `Underpaid` rather than
+            /// refunding the payment and returning `false`.
+            pub fn set_strict_payment(&mut self, strict: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.strict_payment.set(strict);
+                Ok(())
+            }
+
+            /// Sends everything `buy_cupcake` has earned to `to`. Stakes are kept apart and
+            /// can't be withdrawn this way.
+            pub fn withdraw_proceeds(&mut self, to: Address) -> Result<U256, Vec<u8>> {
+                self.only_owner_recovery()?;
+                let proceeds = self.sale_proceeds.get();
+                self.sale_proceeds.set(U256::ZERO);
+                self.send_eth(to, proceeds)?;
+                Ok(proceeds)
+            }
+
+            /// When enabled, cupcakes burned through `eat_cupcake` are taken off the eater's
+            /// lifetime count so they can be claimed again. Off by default.
+            pub fn set_burns_restore_quota(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.burns_restore_quota.set(enabled);
+                Ok(())
+            }
+
+            /// When enabled, `give_cupcake_to(0x0, ...)` gives to the caller instead of
+            /// reverting, so a kiosk button can claim without knowing the user's address.
+            pub fn set_zero_address_fallback(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.zero_address_fallback.set(enabled);
+                Ok(())
+            }
+
+            /// Pauses the contract automatically once more than `limit` claims land within
+            /// `window` seconds. A zero `limit` disables the breaker.
+            pub fn set_circuit_breaker(
+                &mut self,
+                limit: U256,
+                window: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.rate_limit.set(limit);
+                self.rate_window.set(window);
+                self.reset_recent_grants();
+                Ok(())
+            }
+
+            /// Points marketplaces at off-chain JSON describing the cupcake collection.
+            pub fn set_metadata_uri(&mut self, uri: String) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                if uri.len() > MAX_METADATA_URI_LENGTH {
+                    return Err(VendingMachineError::MetadataUriTooLong(MetadataUriTooLong {
+                        length: U256::from(uri.len()),
+                        max: U256::from(MAX_METADATA_URI_LENGTH),
+                    })
+                    .into());
+                }
+                self.metadata_uri.set_str(uri);
+                Ok(())
+            }
+
+            /// Names what balances count, e.g. "cupcakes" or "points". Informational only.
+            pub fn set_unit_label(&mut self, label: String) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                if label.len() > MAX_UNIT_LABEL_LENGTH {
+                    return Err(VendingMachineError::UnitLabelTooLong(UnitLabelTooLong {
+                        length: U256::from(label.len()),
+                        max: U256::from(MAX_UNIT_LABEL_LENGTH),
+                    })
+                    .into());
+                }
+                self.unit_label.set_str(label);
+                Ok(())
+            }
+
+            /// Number of decimals balances are kept in: grants, bonuses and keeper rewards are
+            /// scaled by `10^precision`, and UIs divide by the same factor for display. Supply
+            /// caps, the lifetime cap, the display cap, the minimum transfer fee and the decay
+            /// rate are set in these base units and are not scaled. Locked once any cupcakes
+            /// exist, since existing balances would change meaning.
+            pub fn set_precision(&mut self, precision: u8) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                let supply = self.total_supply.get();
+                if !supply.is_zero() {
+                    return Err(VendingMachineError::PrecisionLocked(PrecisionLocked {
+                        totalSupply: supply,
+                    })
+                    .into());
+                }
+                if precision > MAX_PRECISION {
+                    return Err(VendingMachineError::InvalidPrecision(InvalidPrecision {
+                        precision,
+                        max: MAX_PRECISION,
+                    })
+                    .into());
+                }
+                self.precision.set(U8::from(precision));
+                Ok(())
+            }
+
+            /// Caps how many cupcakes `give_cupcake_to` may ever hand out, in base units (see
+            /// `set_precision`). Zero means unbounded.
+            pub fn set_max_supply(&mut self, max_supply: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.max_supply.set(max_supply);
+                Ok(())
+            }
+
+            /// Caps the circulating supply of `flavor_id` on its own, on top of `max_supply`,
+            /// in base units. Zero means unbounded.
+            pub fn set_flavor_max_supply(
+                &mut self,
+                flavor_id: U256,
+                max_supply: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.register_flavor(flavor_id);
+                self.flavor_max_supply.setter(flavor_id).set(max_supply);
+                Ok(())
+            }
+
+            /// Turns on "use it or lose it" decay: default-flavor balances lose `per_second`
+            /// base units for every second since the holder's last balance change, floored at
+            /// zero. Decay only counts from the moment it was enabled. A new rate also applies
+            /// to decay that hasn't been materialized yet.
+            pub fn set_decay(&mut self, enabled: bool, per_second: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                if enabled && !self.decay_enabled.get() {
+                    self.decay_since
+                        .set(U256::from(self.vm().block_timestamp()));
+                }
+                self.decay_enabled.set(enabled);
+                self.decay_per_second.set(per_second);
+                Ok(())
+            }
+
+            /// Lengthens the cooldown by `step` seconds per cupcake held, up to `max_cooldown`
+            /// seconds. A zero `max_cooldown` leaves the growth uncapped.
+            pub fn set_cooldown_growth(
+                &mut self,
+                step: U256,
+                max_cooldown: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.cooldown_step.set(step);
+                self.max_cooldown.set(max_cooldown);
+                Ok(())
+            }
+
+            /// Caps the length of every batch input so batches have a predictable gas bound.
+            /// Zero leaves batches unbounded.
+            pub fn set_max_batch_size(&mut self, max: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.max_batch_size.set(max);
+                Ok(())
+            }
+
+            /// Gives every address in `users` a fixed `cooldown` in seconds, e.g. for a VIP tier.
+            /// The override replaces the balance-based cooldown entirely.
+            pub fn set_user_cooldowns(
+                &mut self,
+                users: Vec<Address>,
+                cooldown: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.check_batch_size(users.len())?;
+                for user in users {
+                    self.has_cooldown_override.setter(user).set(true);
+                    self.cooldown_overrides.setter(user).set(cooldown);
+                }
+                Ok(())
+            }
+
+            /// Overwrites `user`'s default-flavor distribution time for support cases: zero lets
+            /// them claim right away, a future `timestamp` makes them wait a full cooldown
+            /// from then.
+            pub fn set_distribution_time(
+                &mut self,
+                user: Address,
+                timestamp: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                // Rewinding a user's time would skip their cooldown.
+                self.ensure_config_not_frozen()?;
+                let mut flavor_times = self.cupcake_distribution_times.setter(DEFAULT_FLAVOR);
+                let mut time_accessor = flavor_times.setter(user);
+                let previous = time_accessor.get();
+                time_accessor.set(timestamp);
+                log(
+                    self.vm(),
+                    DistributionTimeSet {
+                        user,
+                        previous,
+                        timestamp,
+                    },
+                );
+                Ok(())
+            }
+
+            /// Configures streaks: consecutive claims each made within `window` seconds of the
+            /// previous one (zero means streaks never lapse). Reaching `threshold` lets a user
+            /// redeem `bonus` cupcakes through `claim_streak_bonus`.
+            pub fn set_streak_config(
+                &mut self,
+                window: U256,
+                threshold: U256,
+                bonus: U256,
+            ) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.streak_window.set(window);
+                self.streak_bonus_threshold.set(threshold);
+                self.streak_bonus_amount.set(bonus);
+                Ok(())
+            }
+
+            /// Debugging aid: returns the raw 32-byte word at storage `slot`, bypassing the
+            /// typed accessors. Packed fields share a word and mappings live at hashed slots,
+            /// so callers must know the layout. Owner-only to keep it off the public surface.
+            pub fn read_raw_slot(&self, slot: U256) -> Result<U256, Vec<u8>> {
+                self.only_owner()?;
+                Ok(U256::from_be_bytes(self.vm().storage_load_bytes32(slot).0))
+            }
+
+            /// Minimum seconds between two owner config changes, zero to disable. Changing it
+            /// counts as a config change itself, so it can't be used to skip the wait.
+            pub fn set_admin_cooldown(&mut self, seconds: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.admin_cooldown.set(seconds);
+                Ok(())
+            }
+
+            pub fn admin_cooldown(&self) -> U256 {
+                self.admin_cooldown.get()
+            }
+
+            /// Number of successful owner-gated mutations so far, config changes and
+            /// moderation alike. `init` doesn't count.
+            pub fn get_admin_action_count(&self) -> U256 {
+                self.admin_action_count.get()
+            }
+
+            /// Seconds a proposed cooldown has to wait before it can be executed. Proposals
+            /// already pending keep the eta they were given.
+            pub fn set_timelock_delay(&mut self, delay: U256) -> Result<(), Vec<u8>> {
+                self.only_owner_config_change()?;
+                self.timelock_delay.set(delay);
+                Ok(())
+            }
+
+            /// Proposes a new base cooldown of `seconds`, executable once `timelock_delay` has
+            /// passed. A new proposal replaces any pending one and restarts the clock.
+            pub fn propose_cooldown(&mut self, seconds: U256) -> Result<U256, Vec<u8>> {
+                self.propose_cooldown_in_units(seconds, COOLDOWN_UNIT_SECONDS)
+            }
+
+            /// Like `propose_cooldown`, with the cooldown given as `count` of `unit`, one of
+            /// the `COOLDOWN_UNIT_*` values. The unit is kept so `get_cooldown_display` can
+            /// show the cooldown the way it was set.
+            pub fn propose_cooldown_in_units(
+                &mut self,
+                count: U256,
+                unit: u8,
+            ) -> Result<U256, Vec<u8>> {
+                self.only_owner_config_change()?;
+                if unit_seconds(unit).is_none() {
+                    return Err(
+                        VendingMachineError::InvalidCooldownUnit(InvalidCooldownUnit { unit })
+                            .into(),
+                    );
+                }
+                let eta = U256::from(self.vm().block_timestamp())
+                    .checked_add(self.timelock_delay.get())
+                    .ok_or(VendingMachineError::Overflow(Overflow {}))?;
+                self.pending_cooldown.set(count);
+                self.pending_cooldown_unit.set(U8::from(unit));
+                self.pending_cooldown_eta.set(eta);
+                Ok(eta)
+            }
+
+            /// Applies the pending cooldown once its eta has been reached.
+            pub fn execute_cooldown(&mut self) -> Result<(), Vec<u8>> {
+                self.only_owner_action()?;
+                self.ensure_config_not_frozen()?;
+                let eta = self.pending_cooldown_eta.get();
+                if eta.is_zero() {
+                    return Err(VendingMachineError::NoPendingCooldown(NoPendingCooldown {}).into());
+                }
+                if U256::from(self.vm().block_timestamp()) < eta {
+                    return Err(VendingMachineError::TimelockNotElapsed(TimelockNotElapsed {
+                        eta,
+                    })
+                    .into());
+                }
+                self.has_base_cooldown.set(true);
+                self.base_cooldown.set(self.pending_cooldown.get());
+                self.cooldown_unit.set(self.pending_cooldown_unit.get());
+                self.pending_cooldown.set(U256::ZERO);
+                self.pending_cooldown_unit.set(U8::ZERO);
+                self.pending_cooldown_eta.set(U256::ZERO);
+                Ok(())
+            }
+        }
+
+        /// Time at which `init` was called. Distribution phases are measured from here.
+        pub fn start_time(&self) -> U256 {
+            self.start_time.get()
+        }
+
+        /// Seconds since `init`, for "running for X" displays.
+        pub fn contract_age(&self) -> Result<U256, Vec<u8>> {
+            Ok(U256::from(self.vm().block_timestamp()).saturating_sub(self.start_time.get()))
+        }
+
+        /// Cooldown before balance-based growth, `COOLDOWN_SECONDS` unless changed through
+        /// `propose_cooldown`.
+        pub fn base_cooldown(&self) -> U256 {
+            self.base_cooldown_seconds()
+        }
+
+        /// Base cooldown as the count and `COOLDOWN_UNIT_*` unit it was set with.
+        pub fn get_cooldown_display(&self) -> (U256, u8) {
+            if self.has_base_cooldown.get() {
+                (self.base_cooldown.get(), self.cooldown_unit.get().to())
+            } else {
+                (U256::from(COOLDOWN_SECONDS), COOLDOWN_UNIT_SECONDS)
+            }
+        }
+
+        pub fn timelock_delay(&self) -> U256 {
+            self.timelock_delay.get()
+        }
+
+        /// Proposed cooldown count and the time it becomes executable. An eta of zero means
+        /// nothing is pending.
+        pub fn pending_cooldown(&self) -> (U256, U256) {
+            (self.pending_cooldown.get(), self.pending_cooldown_eta.get())
+        }
+
+        pub fn grace_period(&self) -> U256 {
+            self.grace_period.get()
+        }
+
+        pub fn block_contracts(&self) -> bool {
+            self.block_contracts.get()
+        }
+
+        pub fn reward_token(&self) -> Address {
+            self.reward_token.get()
+        }
+
+        pub fn reward_amount(&self) -> U256 {
+            self.reward_amount.get()
+        }
+
+        pub fn daily_limit(&self) -> U256 {
+            self.daily_limit.get()
+        }
+
+        pub fn is_cooldown_waived(&self) -> bool {
+            U256::from(self.vm().block_timestamp()) < self.cooldown_waived_until.get()
+        }
+
+        pub fn min_account_age(&self) -> U256 {
+            self.min_account_age.get()
+        }
+
+        pub fn is_blacklisted(&self, user: Address) -> bool {
+            self.blacklisted.get(user)
+        }
+
+        pub fn is_frozen(&self, user: Address) -> bool {
+            self.frozen.get(user)
+        }
+
+        pub fn paused(&self) -> bool {
+            self.paused.get()
+        }
+
+        pub fn metadata_uri(&self) -> String {
+            self.metadata_uri.get_string()
+        }
+
+        /// What balances count, empty until the owner sets one.
+        pub fn unit_label(&self) -> String {
+            self.unit_label.get_string()
+        }
+
+        /// Decimals balances are kept in; divide by `10^precision` to display whole units.
+        pub fn precision(&self) -> u8 {
+            self.precision.get().to()
+        }
+
+        /// Logs a `MetricsSnapshot` of the current aggregates for event-driven indexers.
+        /// Nothing is written; it takes `&mut self` only so the ABI doesn't mark it `view`,
+        /// since a static call would discard the log.
+        pub fn emit_metrics(&mut self) {
+            log(
+                self.vm(),
+                MetricsSnapshot {
+                    totalDistributed: self.total_distributed.get(),
+                    totalSupply: self.total_supply.get(),
+                    holders: self.holder_count.get(),
+                    timestamp: U256::from(self.vm().block_timestamp()),
+                },
+            );
+        }
+
+        /// Account with the largest default-flavor balance and that balance, `(0, 0)` when
+        /// nobody holds any. Ties go to whichever became a holder first. Scans every account
+        /// that has ever held a cupcake, so gas grows linearly with that number; meant for
+        /// off-chain calls.
+        pub fn top_holder(&self) -> Result<(Address, U256), Vec<u8>> {
+            let mut top = (Address::ZERO, U256::ZERO);
+            for i in 0..self.holder_list.len() {
+                let Some(holder) = self.holder_list.get(i) else {
+                    continue;
+                };
+                let balance = self.effective_balance(holder);
+                if balance > top.1 {
+                    top = (holder, balance);
+                }
+            }
+            Ok(top)
+        }
+
+        /// Semantic version of this build, for checking what is deployed at an address.
+        pub fn version(&self) -> String {
+            VERSION.into()
+        }
+
+        /// Total number of cupcakes currently in circulation.
+        pub fn total_supply(&self) -> U256 {
+            self.total_supply.get()
+        }
+
+        /// Longest accepted batch input, zero if unbounded.
+        pub fn max_batch_size(&self) -> U256 {
+            self.max_batch_size.get()
+        }
+
+        /// Supply cap for distributions, zero if unbounded.
+        pub fn max_supply(&self) -> U256 {
+            self.max_supply.get()
+        }
+
+        /// All the contract's limits in one call. `rateLimit` grants per `rateWindow` seconds
+        /// is the circuit breaker's threshold.
+        pub fn get_limits(&self) -> Limits {
+            Limits {
+                maxSupply: self.max_supply.get(),
+                dailyLimit: self.daily_limit.get(),
+                lifetimeCap: self.lifetime_cap.get(),
+                rateLimit: self.rate_limit.get(),
+                rateWindow: self.rate_window.get(),
+                maxClaimsPerBlock: self.max_claims_per_block.get(),
+                maxBatchSize: self.max_batch_size.get(),
+            }
+        }
+
+        /// Cupcakes handed out by `give_cupcake_to` so far, across all flavors.
+        pub fn total_distributed(&self) -> U256 {
+            self.total_distributed.get()
+        }
+
+        /// Tier of `user`'s default-flavor balance: the number of thresholds it reaches.
+        pub fn get_tier(&self, user: Address) -> Result<u8, Vec<u8>> {
+            let balance = self.effective_balance(user);
+            let reached = (0..self.tier_thresholds.len())
+                .filter_map(|i| self.tier_thresholds.get(i))
+                .take_while(|threshold| balance >= *threshold)
+                .count();
+            Ok(reached as u8)
+        }
+
+        /// Whether `a` and `b` are in the same balance tier.
+        pub fn same_tier(&self, a: Address, b: Address) -> Result<bool, Vec<u8>> {
+            Ok(self.get_tier(a)? == self.get_tier(b)?)
+        }
+
+        /// EIP-712 domain separator used by `permit`, built from the contract name, version,
+        /// chain id and address. Served from the copy cached by `init` unless the chain id
+        /// has changed since, e.g. after a fork.
+        pub fn domain_separator(&self) -> B256 {
+            let cached = self.cached_domain_separator.get();
+            if !cached.is_zero() && self.cached_chain_id.get() == U256::from(self.vm().chain_id()) {
+                return cached;
+            }
+            self.eip712_domain().separator()
+        }
+
+        /// Default-flavor cooldown that applies to `user` right now and the rule that decided
+        /// it, in the order `give_cupcake_to` applies them: 3 = waived, 2 = exempt during the
+        /// grace period (both report a zero cooldown), 1 = per-user override,
+        /// 4 = balance-scaled, 0 = global.
+        pub fn explain_cooldown(&self, user: Address) -> Result<(U256, u8), Vec<u8>> {
+            if self.is_cooldown_waived() {
+                return Ok((U256::ZERO, COOLDOWN_RULE_WAIVED));
+            }
+            if self.in_grace_period() {
+                return Ok((U256::ZERO, COOLDOWN_RULE_EXEMPT));
+            }
+            let cooldown = self.effective_cooldown(user, DEFAULT_FLAVOR);
+            let rule = if self.has_cooldown_override.get(user) {
+                COOLDOWN_RULE_OVERRIDE
+            } else if !self.cooldown_growth(user, DEFAULT_FLAVOR).is_zero() {
+                COOLDOWN_RULE_BALANCE_SCALED
+            } else {
+                COOLDOWN_RULE_GLOBAL
+            };
+            Ok((cooldown, rule))
+        }
+
+        /// Coarse gas bucket for `user`'s next default-flavor claim: 0 = first claim, which
+        /// writes a fresh distribution-time slot, 1 = repeat claim, 2 = claim that also mints
+        /// an auto-compound bonus. A bonus claim reports 2 even if it is also the first.
+        pub fn claim_complexity(&self, user: Address) -> Result<u8, Vec<u8>> {
+            if self.auto_compound.get(user) {
+                return Ok(COMPLEXITY_BONUS_CLAIM);
+            }
+            let last_distribution = self
+                .cupcake_distribution_times
+                .getter(DEFAULT_FLAVOR)
+                .get(user);
+            Ok(if last_distribution.is_zero() {
+                COMPLEXITY_FIRST_CLAIM
+            } else {
+                COMPLEXITY_REPEAT_CLAIM
+            })
+        }
+
+        /// Running hash of `user`'s claims, zero before the first one. See `next_history_root`
+        /// for how each claim is folded in.
+        pub fn get_history_root(&self, user: Address) -> B256 {
+            self.history_roots.get(user)
+        }
+
+        /// Every flavor that has been capped or given out, in the order first seen.
+        pub fn list_flavors(&self) -> Result<Vec<U256>, Vec<u8>> {
+            Ok((0..self.flavor_ids.len())
+                .filter_map(|i| self.flavor_ids.get(i))
+                .collect())
+        }
+
+        /// Full claims left before `max_supply` is reached at the current grant amount,
+        /// rounded down. `U256::MAX` if there is no cap, or if halving has taken the grant
+        /// to zero so claims no longer draw on the supply.
+        pub fn claims_until_sold_out(&self) -> Result<U256, Vec<u8>> {
+            let max_supply = self.max_supply.get();
+            let grant = self.current_grant_amount();
+            if max_supply.is_zero() || grant.is_zero() {
+                return Ok(U256::MAX);
+            }
+            Ok(max_supply.saturating_sub(self.total_distributed.get()) / grant)
+        }
+
+        /// Cupcakes of `flavor_id` currently in circulation.
+        pub fn flavor_total_supply(&self, flavor_id: U256) -> U256 {
+            self.flavor_total_supply.get(flavor_id)
+        }
+
+        /// Supply cap for `flavor_id`, zero if unbounded.
+        pub fn flavor_max_supply(&self, flavor_id: U256) -> U256 {
+            self.flavor_max_supply.get(flavor_id)
+        }
+
+        /// Cupcakes of `flavor_id` that can still be given before its own cap is hit,
+        /// `U256::MAX` if the flavor is uncapped. The global `max_supply` isn't included.
+        pub fn flavor_remaining(&self, flavor_id: U256) -> U256 {
+            let max_supply = self.flavor_max_supply.get(flavor_id);
+            if max_supply.is_zero() {
+                return U256::MAX;
+            }
+            max_supply.saturating_sub(self.flavor_total_supply.get(flavor_id))
+        }
+
+        /// Whether the supply cap is set and has been reached.
+        pub fn is_sold_out(&self) -> Result<bool, Vec<u8>> {
+            let max_supply = self.max_supply.get();
+            Ok(!max_supply.is_zero() && self.total_distributed.get() >= max_supply)
+        }
+
+        /// Gives `user_address` a cupcake of `flavor_id`. Each flavor has its own cooldown.
+        pub fn give_cupcake_to(
+            &mut self,
+            user_address: Address,
+            flavor_id: U256,
+        ) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let user_address = self.resolve_recipient(user_address)?;
+            self.ensure_not_blacklisted(user_address)?;
+            self.ensure_not_frozen(user_address)?;
+            self.ensure_staked(user_address)?;
+
+            // Reject contract recipients if the guard is enabled.
+            if self.block_contracts.get() && self.vm().code_size(user_address) > 0 {
+                return Err(VendingMachineError::ContractRecipient(ContractRecipient {
+                    recipient: user_address,
+                })
+                .into());
+            }
+
+            // Turn away accounts that are younger than the minimum age.
+            if !self.check_account_age(user_address)? {
+                console!("Account too new: first sighting recorded, try again later");
+                return Ok(false);
+            }
+
+            // Check if the user can receive a cupcake.
+            let remaining = self.cooldown_remaining(user_address, flavor_id);
+            let user_can_receive_cupcake = remaining.is_zero();
+
+            if user_can_receive_cupcake {
+                // Make sure the grant fits under the supply cap.
+                let amount = self.claim_grant_amount(user_address, flavor_id)?;
+                if self.exceeds_supply_cap(amount) {
+                    return Err(VendingMachineError::SoldOut(SoldOut {
+                        maxSupply: self.max_supply.get(),
+                    })
+                    .into());
+                }
+                if self.exceeds_flavor_cap(flavor_id, amount) {
+                    return Err(VendingMachineError::FlavorSoldOut(FlavorSoldOut {
+                        flavorId: flavor_id,
+                        maxSupply: self.flavor_max_supply.get(flavor_id),
+                    })
+                    .into());
+                }
+                if self.exceeds_lifetime_cap(user_address, amount) {
+                    return Err(VendingMachineError::LifetimeCapReached(LifetimeCapReached {
+                        user: user_address,
+                        cap: self.lifetime_cap.get(),
+                    })
+                    .into());
+                }
+
+                let block_claims = self.claims_this_block();
+                let max_per_block = self.max_claims_per_block.get();
+                if !max_per_block.is_zero() && block_claims >= max_per_block {
+                    return Err(VendingMachineError::BlockClaimLimit(BlockClaimLimit {
+                        blockNumber: U256::from(self.vm().block_number()),
+                        max: max_per_block,
+                    })
+                    .into());
+                }
+
+                // A spike trips the breaker instead of granting. Returning an error would roll
+                // the pause back, so the claim is turned away with `Ok(false)`.
+                if self.trip_circuit_breaker() {
+                    return Ok(false);
+                }
+
+                // Count the claim against today's limit and this block's.
+                self.record_daily_claim(user_address)?;
+                self.claims_block.set(U256::from(self.vm().block_number()));
+                self.claims_in_block.set(block_claims + U256::from(1));
+
+                // Charge the maintenance fee on what the user already holds.
+                let fee = self.claim_fee_for(user_address, flavor_id);
+                if !fee.is_zero() {
+                    self.burn(user_address, flavor_id, fee, BALANCE_REASON_BURN)?;
+                    log(
+                        self.vm(),
+                        Transfer {
+                            from: user_address,
+                            to: Address::ZERO,
+                            value: fee,
+                        },
+                    );
+                }
+
+                // Increment the user's cupcake balance.
+                let received = self.lifetime_received.get(user_address) + amount;
+                self.lifetime_received.setter(user_address).set(received);
+                self.register_flavor(flavor_id);
+                self.mint(user_address, flavor_id, amount, BALANCE_REASON_GRANT)?;
+                let distributed = self.total_distributed.get() + amount;
+                self.total_distributed.set(distributed);
+
+                // Get current timestamp using the VM pattern BEFORE creating the mutable borrow
+                let new_distribution_time = self.vm().block_timestamp();
+
+                // Extend the streak if this claim follows the previous one closely enough.
+                // Streaks only follow the default flavor.
+                if flavor_id == DEFAULT_FLAVOR {
+                    // Any auto-compound bonus is already part of `amount`.
+                    self.update_streak(user_address, U256::from(new_distribution_time));
+                }
+
+                // Update the distribution time to the current time.
+                let mut flavor_times = self.cupcake_distribution_times.setter(flavor_id);
+                let mut time_accessor = flavor_times.setter(user_address);
+                time_accessor.set(U256::from(new_distribution_time));
+
+                // Fold the claim into the user's running history hash.
+                let history_root = Self::next_history_root(
+                    self.history_roots.get(user_address),
+                    U256::from(new_distribution_time),
+                    amount,
+                );
+                self.history_roots.setter(user_address).set(history_root);
+
+                // Bump the user's claim count and publish it for activity feeds.
+                let mut count_accessor = self.claim_counts.setter(user_address);
+                let claim_number = count_accessor.get() + U256::from(1);
+                count_accessor.set(claim_number);
+                log(
+                    self.vm(),
+                    UserClaim {
+                        user: user_address,
+                        claimNumber: claim_number,
+                        timestamp: U256::from(new_distribution_time),
+                    },
+                );
+
+                // Pay out the reward token, if one is configured.
+                let reward_token = self.reward_token.get();
+                if !reward_token.is_zero() {
+                    self.erc20_transfer(reward_token, user_address, self.reward_amount.get())?;
+                }
+
+                self.on_cupcake_given(user_address, amount);
+                Ok(true)
+            } else {
+                // User must wait before receiving another cupcake.
+                console!("{}", cooldown_message(remaining));
+                Ok(false)
+            }
+        }
+
+        /// Default-flavor overload of `giveCupcakeTo`, kept for callers of the original ABI.
+        #[selector(name = "giveCupcakeTo")]
+        pub fn give_default_cupcake_to(&mut self, user_address: Address) -> Result<bool, Vec<u8>> {
+            self.give_cupcake_to(user_address, DEFAULT_FLAVOR)
+        }
+
+        /// Schedules a default-flavor claim for the caller, to be carried out by anyone
+        /// calling `execute_scheduled` from `eligible_at` on. Replaces any earlier schedule.
+        pub fn schedule_claim(&mut self, eligible_at: U256) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            self.scheduled_claims.setter(sender).set(eligible_at);
+            Ok(())
+        }
+
+        /// Time from which `user`'s scheduled claim can be executed, zero if none is.
+        pub fn scheduled_claim(&self, user: Address) -> U256 {
+            self.scheduled_claims.get(user)
+        }
+
+        /// Carries out `user`'s scheduled claim through `give_cupcake_to`, so every regular
+        /// check still applies. Reverts before the scheduled time; a claim still turned
+        /// away by the cooldown returns `false` and stays scheduled. A successful grant
+        /// clears the schedule.
+        pub fn execute_scheduled(&mut self, user: Address) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let eligible_at = self.scheduled_claims.get(user);
+            if eligible_at.is_zero() {
+                return Err(
+                    VendingMachineError::NoScheduledClaim(NoScheduledClaim { user }).into(),
+                );
+            }
+            if U256::from(self.vm().block_timestamp()) < eligible_at {
+                return Err(
+                    VendingMachineError::ScheduledClaimTooEarly(ScheduledClaimTooEarly {
+                        user,
+                        eligibleAt: eligible_at,
+                    })
+                    .into(),
+                );
+            }
+            let given = self.give_cupcake_to(user, DEFAULT_FLAVOR)?;
+            if given {
+                self.scheduled_claims.setter(user).set(U256::ZERO);
+            }
+            Ok(given)
+        }
+
+        /// Claims a cupcake for the caller as long as `deadline` hasn't passed.
+        /// The regular cooldown still applies.
+        pub fn redeem_voucher(&mut self, deadline: U256) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            if U256::from(self.vm().block_timestamp()) > deadline {
+                return Err(
+                    VendingMachineError::VoucherExpired(VoucherExpired { deadline }).into(),
+                );
+            }
+            let sender = self.vm().msg_sender();
+            self.give_cupcake_to(sender, DEFAULT_FLAVOR)
+        }
+
+        /// Claims a cupcake for the caller if `proof` shows they are in the merkle allowlist.
+        /// Leaves are `keccak256(address)` and pairs are hashed in sorted order. Each address
+        /// can claim once per root; the regular cooldown and other checks still apply, and a
+        /// claim turned away by them doesn't use up the allowance.
+        pub fn claim_with_proof(&mut self, proof: Vec<B256>) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            let root = self.merkle_root.get();
+            if root.is_zero() || Self::merkle_root_from(Self::merkle_leaf(sender), &proof) != root {
+                return Err(VendingMachineError::InvalidProof(InvalidProof {}).into());
+            }
+            if self.merkle_claims.getter(root).get(sender) {
+                return Err(VendingMachineError::AlreadyClaimed(AlreadyClaimed {
+                    user: sender,
+                    root,
+                })
+                .into());
+            }
+
+            let given = self.give_cupcake_to(sender, DEFAULT_FLAVOR)?;
+            if given {
+                self.merkle_claims.setter(root).setter(sender).set(true);
+            }
+            Ok(given)
+        }
+
+        /// Root of the current merkle allowlist, zero if none is set. Returned as `bytes32`.
+        pub fn get_merkle_root(&self) -> B256 {
+            self.merkle_root.get()
+        }
+
+        /// Whether `user` already claimed against the current merkle root. Claims are
+        /// tracked per root, so this resets whenever the owner sets a new one.
+        pub fn has_claimed(&self, user: Address) -> bool {
+            self.merkle_claims.getter(self.merkle_root.get()).get(user)
+        }
+
+        /// Like `give_cupcake_to` for the default flavor, but reports why the user was
+        /// turned away as one of the `REASON_*` codes instead of reverting:
+        /// 0 = given, 1 = cooldown, 2 = paused, 3 = sold out, 4 = blacklisted,
+        /// 5 = contract recipient, 6 = account too new, 7 = daily limit reached,
+        /// 8 = frozen, 9 = lifetime cap reached, 10 = stake too low,
+        /// 11 = tripped the circuit breaker, 12 = zero recipient without the fallback,
+        /// 13 = block claim limit reached.
+        /// A failing reward transfer still reverts.
+        pub fn try_give(&mut self, user: Address) -> Result<u8, Vec<u8>> {
+            if user.is_zero() && !self.zero_address_fallback.get() {
+                return Ok(REASON_ZERO_RECIPIENT);
+            }
+            let user = self.resolve_recipient(user)?;
+            let reason = self.reject_reason(user)?;
+            self.note_first_sighting(user, reason)?;
+            if reason != REASON_OK {
+                return Ok(reason);
+            }
+            // Every other way of being turned away was ruled out above.
+            if !self.give_cupcake_to(user, DEFAULT_FLAVOR)? {
+                return Ok(REASON_CIRCUIT_BREAKER);
+            }
+            Ok(REASON_OK)
+        }
+
+        /// Gives a default-flavor cupcake to each of `users` and returns how many were given.
+        ///
+        /// Users that `try_give` would turn away for any reason are ineligible, including a
+        /// repeated address that is cooling down after its first grant. In `strict` mode any
+        /// ineligible user reverts the whole batch with `IneligibleRecipient`; otherwise they
+        /// are skipped. Other errors revert either way. If the circuit breaker trips, the batch stops there and keeps the
+        /// grants made so far, so the pause sticks.
+        ///
+        /// The caller earns the keeper reward for every grant made, trimmed to whatever is
+        /// left under the supply cap.
+        pub fn give_cupcakes_to(
+            &mut self,
+            users: Vec<Address>,
+            strict: bool,
+        ) -> Result<U256, Vec<u8>> {
+            self.when_not_paused()?;
+            self.check_batch_size(users.len())?;
+            let users = self.resolve_recipients(users)?;
+            if strict {
+                if let Some(user) = users.iter().find(|user| !self.is_eligible(**user)) {
+                    return Err(
+                        VendingMachineError::IneligibleRecipient(IneligibleRecipient {
+                            user: *user,
+                        })
+                        .into(),
+                    );
+                }
+            }
+
+            let mut given = U256::ZERO;
+            for user in users {
+                if self.paused.get() {
+                    break;
+                }
+                let reason = self.reject_reason(user)?;
+                if reason != REASON_OK {
+                    if strict {
+                        return Err(VendingMachineError::IneligibleRecipient(
+                            IneligibleRecipient { user },
+                        )
+                        .into());
+                    }
+                    self.note_first_sighting(user, reason)?;
+                    continue;
+                }
+                // Only the circuit breaker can still turn the claim away, which pauses.
+                if self.give_cupcake_to(user, DEFAULT_FLAVOR)? {
+                    given += U256::from(1);
+                }
+            }
+            self.pay_keeper(given)?;
+            Ok(given)
+        }
+
+        /// Like non-strict `give_cupcakes_to`, but reports one flag per entry of `users`, in
+        /// input order: `true` if that user was given a cupcake, `false` if they were skipped.
+        /// Everyone after a circuit-breaker trip is skipped.
+        pub fn give_cupcakes_detailed(
+            &mut self,
+            users: Vec<Address>,
+        ) -> Result<Vec<bool>, Vec<u8>> {
+            self.when_not_paused()?;
+            self.check_batch_size(users.len())?;
+            let users = self.resolve_recipients(users)?;
+            let mut results = Vec::with_capacity(users.len());
+            let mut given = U256::ZERO;
+            for user in users {
+                let granted = if self.paused.get() {
+                    false
+                } else {
+                    let reason = self.reject_reason(user)?;
+                    self.note_first_sighting(user, reason)?;
+                    reason == REASON_OK && self.give_cupcake_to(user, DEFAULT_FLAVOR)?
+                };
+                if granted {
+                    given += U256::from(1);
+                }
+                results.push(granted);
+            }
+            self.pay_keeper(given)?;
+            Ok(results)
+        }
+
+        /// Balance of `flavor_id` held by `user_address`, net of pending decay and capped at
+        /// `display_cap` when one is set.
+        pub fn get_cupcake_balance_for(
+            &self,
+            user_address: Address,
+            flavor_id: U256,
+        ) -> Result<U256, Vec<u8>> {
+            let balance = self.effective_flavor_balance(user_address, flavor_id);
+            let cap = self.display_cap.get();
+            if cap.is_zero() {
+                return Ok(balance);
+            }
+            Ok(balance.min(cap))
+        }
+
+        pub fn display_cap(&self) -> U256 {
+            self.display_cap.get()
+        }
+
+        /// Default-flavor balance exactly as stored for `user`. Unlike
+        /// `get_cupcake_balance_for` it neither subtracts decay that hasn't been written to
+        /// storage yet nor applies `display_cap`, so it can read higher than what the user
+        /// could actually transfer.
+        pub fn raw_balance_of(&self, user: Address) -> Result<U256, Vec<u8>> {
+            Ok(self.flavor_balance(user, DEFAULT_FLAVOR))
+        }
+
+        /// Default-flavor balance of `user` net of decay not yet written to storage. Equal
+        /// to the stored balance while decay is off.
+        pub fn effective_balance(&self, user: Address) -> U256 {
+            self.effective_flavor_balance(user, DEFAULT_FLAVOR)
+        }
+
+        pub fn decay_per_second(&self) -> U256 {
+            self.decay_per_second.get()
+        }
+
+        pub fn is_decay_enabled(&self) -> bool {
+            self.decay_enabled.get()
+        }
+
+        /// Default-flavor overload of `getCupcakeBalanceFor`, kept for callers of the original ABI.
+        #[selector(name = "getCupcakeBalanceFor")]
+        pub fn get_default_cupcake_balance_for(
+            &self,
+            user_address: Address,
+        ) -> Result<U256, Vec<u8>> {
+            self.get_cupcake_balance_for(user_address, DEFAULT_FLAVOR)
+        }
+
+        /// Whether `user_address` could receive a default-flavor cupcake right now.
+        pub fn can_receive_cupcake(&self, user_address: Address) -> Result<bool, Vec<u8>> {
+            Ok(self
+                .cooldown_remaining(user_address, DEFAULT_FLAVOR)
+                .is_zero())
+        }
+
+        /// Default-flavor cooldown that applies to `user` given their current balance.
+        pub fn get_effective_cooldown(&self, user: Address) -> Result<U256, Vec<u8>> {
+            Ok(self.effective_cooldown(user, DEFAULT_FLAVOR))
+        }
+
+        /// Seconds left before `user_address` can receive another default-flavor cupcake,
+        /// zero if eligible.
+        pub fn time_until_next_cupcake(&self, user_address: Address) -> Result<U256, Vec<u8>> {
+            Ok(self.cooldown_remaining(user_address, DEFAULT_FLAVOR))
+        }
+
+        /// Timestamp from which `user` can receive another default-flavor cupcake. Returns
+        /// the current block time if they already can.
+        pub fn next_eligible_time(&self, user: Address) -> Result<U256, Vec<u8>> {
+            Ok(U256::from(self.vm().block_timestamp())
+                .saturating_add(self.cooldown_remaining(user, DEFAULT_FLAVOR)))
+        }
+
+        /// Default-flavor balance and last distribution time of `user` in one word: bits
+        /// 0..128 hold the balance and bits 128..256 the timestamp, each saturating at
+        /// `u128::MAX`.
+        pub fn packed_user_state(&self, user: Address) -> Result<U256, Vec<u8>> {
+            let low = U256::from(u128::MAX);
+            let balance = self.effective_flavor_balance(user, DEFAULT_FLAVOR).min(low);
+            let last = self
+                .cupcake_distribution_times
+                .getter(DEFAULT_FLAVOR)
+                .get(user)
+                .min(low);
+            Ok((last << 128) | balance)
+        }
+
+        /// Seconds since `user` last received a default-flavor cupcake, zero if they never
+        /// have. Unlike `time_until_next_cupcake` this keeps growing past the cooldown.
+        pub fn seconds_since_last_claim(&self, user: Address) -> Result<U256, Vec<u8>> {
+            if self
+                .cupcake_distribution_times
+                .getter(DEFAULT_FLAVOR)
+                .get(user)
+                .is_zero()
+            {
+                return Ok(U256::ZERO);
+            }
+            Ok(self.elapsed_since(user, DEFAULT_FLAVOR))
+        }
+
+        /// `next_eligible_time` for each of `users`, in order.
+        pub fn batch_next_eligible(&self, users: Vec<Address>) -> Result<Vec<U256>, Vec<u8>> {
+            self.check_batch_size(users.len())?;
+            users
+                .into_iter()
+                .map(|user| self.next_eligible_time(user))
+                .collect()
+        }
+
+        /// `(can_receive_cupcake, time_until_next_cupcake)` for each of `users`, in order.
+        pub fn batch_status(&self, users: Vec<Address>) -> Result<Vec<(bool, U256)>, Vec<u8>> {
+            self.check_batch_size(users.len())?;
+            Ok(users
+                .into_iter()
+                .map(|user| {
+                    let remaining = self.cooldown_remaining(user, DEFAULT_FLAVOR);
+                    (remaining.is_zero(), remaining)
+                })
+                .collect())
+        }
+
+        /// Zeroes `user`'s default-flavor distribution time for a storage refund. Anyone
+        /// may call it, but only once the user's own cooldown has fully elapsed, ignoring
+        /// any waiver or grace period, so it can't be used to skip a wait. It also refuses
+        /// while the user has a streak that a bonus could still be earned on.
+        pub fn clear_distribution_time(&mut self, user: Address) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            let elapsed = self.elapsed_since(user, DEFAULT_FLAVOR);
+            let remaining = self
+                .effective_cooldown(user, DEFAULT_FLAVOR)
+                .saturating_sub(elapsed);
+            if !remaining.is_zero() {
+                return Err(VendingMachineError::CooldownActive(CooldownActive {
+                    user,
+                    remaining,
+                })
+                .into());
+            }
+
+            let streak = self.streaks.get(user);
+            let window = self.streak_window.get();
+            let streak_alive = window.is_zero() || elapsed <= window;
+            if !self.streak_bonus_threshold.get().is_zero() && !streak.is_zero() && streak_alive {
+                return Err(
+                    VendingMachineError::StreakActive(StreakActive { user, streak }).into(),
+                );
+            }
+
+            self.cupcake_distribution_times
+                .setter(DEFAULT_FLAVOR)
+                .setter(user)
+                .set(U256::ZERO);
+            Ok(())
+        }
+
+        pub fn get_streak(&self, user: Address) -> U256 {
+            self.streaks.get(user)
+        }
+
+        /// Trades the caller's streak for the configured bonus. The streak restarts from zero.
+        pub fn claim_streak_bonus(&mut self) -> Result<U256, Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            let streak = self.streaks.get(sender);
+            let threshold = self.streak_bonus_threshold.get();
+            if threshold.is_zero() || streak < threshold {
+                return Err(VendingMachineError::StreakTooShort(StreakTooShort {
+                    streak,
+                    threshold,
+                })
+                .into());
+            }
+
+            let bonus = self.to_base_units(self.streak_bonus_amount.get());
+            self.streaks.setter(sender).set(U256::ZERO);
+            self.mint(sender, DEFAULT_FLAVOR, bonus, BALANCE_REASON_GRANT)?;
+            Ok(bonus)
+        }
+
+        /// Opts the caller in or out of auto-compounding: while enabled, every default-flavor
+        /// claim also credits a bonus equal to the caller's streak after that claim.
+        pub fn enable_auto_compound(&mut self, enabled: bool) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            self.auto_compound.setter(sender).set(enabled);
+            Ok(())
+        }
+
+        pub fn is_auto_compound_enabled(&self, user: Address) -> bool {
+            self.auto_compound.get(user)
+        }
+
+        /// Returns `(balance, eligible_now, seconds_until_eligible)` for a claim screen in one
+        /// call, for the default flavor.
+        pub fn user_status(&self, user: Address) -> Result<(U256, bool, U256), Vec<u8>> {
+            let remaining = self.cooldown_remaining(user, DEFAULT_FLAVOR);
+            Ok((self.effective_balance(user), remaining.is_zero(), remaining))
+        }
+
+        /// How many cupcakes `user_address` would receive from a successful default-flavor
+        /// claim right now, including any auto-compound bonus.
+        ///
+        /// Every adjustment to the payout is composed here so the preview and the actual
+        /// grant can never disagree.
+        pub fn effective_grant_amount(&self, user_address: Address) -> Result<U256, Vec<u8>> {
+            self.claim_grant_amount(user_address, DEFAULT_FLAVOR)
+        }
+
+        /// Whether `user`'s next successful claim would include the `first_claim_bonus`,
+        /// i.e. a bonus is configured and they have never claimed. Keyed on the claim count
+        /// rather than the distribution time, which can be cleared or reset.
+        pub fn would_get_first_bonus(&self, user: Address) -> bool {
+            !self.first_claim_bonus.get().is_zero() && self.claim_counts.get(user).is_zero()
+        }
+
+        pub fn first_claim_bonus(&self) -> U256 {
+            self.first_claim_bonus.get()
+        }
+
+        /// Grant after halving: the base `grant_amount` shifted right once per elapsed
+        /// `halving_interval`, floored at `min_grant_amount`, then scaled by
+        /// `10^precision`.
+        pub fn current_grant_amount(&self) -> U256 {
+            let grant = self.grant_amount.get();
+            let base = if grant.is_zero() {
+                U256::from(1)
+            } else {
+                grant
+            };
+            let interval = self.halving_interval.get();
+            let units = if interval.is_zero() {
+                base
+            } else {
+                let elapsed =
+                    U256::from(self.vm().block_timestamp()).saturating_sub(self.start_time.get());
+                let epochs = elapsed / interval;
+                let halved = if epochs >= U256::from(256) {
+                    U256::ZERO
+                } else {
+                    base >> epochs.to::<usize>()
+                };
+                halved.max(self.min_grant_amount.get().min(base))
+            };
+            self.to_base_units(units)
+        }
+
+        /// Default-flavor balance `user` would have after `claims` more successful claims at
+        /// the current grant amount, plus the first-claim bonus if it's still due. Assumes
+        /// nothing else changes: streak and auto-compound bonuses, the claim fee, decay and
+        /// supply caps are not modelled.
+        pub fn projected_balance(&self, user: Address, claims: U256) -> Result<U256, Vec<u8>> {
+            let bonus = if !claims.is_zero() && self.would_get_first_bonus(user) {
+                self.to_base_units(self.first_claim_bonus.get())
+            } else {
+                U256::ZERO
+            };
+            Ok(self
+                .current_grant_amount()
+                .checked_mul(claims)
+                .and_then(|granted| granted.checked_add(bonus))
+                .and_then(|granted| granted.checked_add(self.effective_balance(user)))
+                .ok_or(VendingMachineError::Overflow(Overflow {}))?)
+        }
+
+        /// Sums the default-flavor balances of `users`. Duplicate addresses are counted every time they appear.
+        pub fn sum_balances(&self, users: Vec<Address>) -> Result<U256, Vec<u8>> {
+            self.check_batch_size(users.len())?;
+            let mut total = U256::ZERO;
+            for user in users {
+                total = total
+                    .checked_add(self.effective_balance(user))
+                    .ok_or(VendingMachineError::Overflow(Overflow {}))?;
+            }
+            Ok(total)
+        }
+
+        /// Total supply at the end of `block`, found by binary search over the supply
+        /// checkpoints. Blocks before the first mint report zero.
+        pub fn total_supply_at(&self, block: u64) -> Result<U256, Vec<u8>> {
+            let current = self.vm().block_number();
+            if block > current {
+                return Err(VendingMachineError::FutureBlock(FutureBlock {
+                    block: U256::from(block),
+                    current: U256::from(current),
+                })
+                .into());
+            }
+
+            // Find the first checkpoint after `block`; the one before it is the answer.
+            let block = U256::from(block);
+            let (mut low, mut high) = (0, self.supply_checkpoint_blocks.len());
+            while low < high {
+                let mid = (low + high) / 2;
+                if self.supply_checkpoint_blocks.get(mid).unwrap_or_default() <= block {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            if low == 0 {
+                return Ok(U256::ZERO);
+            }
+            Ok(self
+                .supply_checkpoint_values
+                .get(low - 1)
+                .unwrap_or_default())
+        }
+
+        /// Compares the default-flavor balances of `a` and `b`: returns whether `a` holds at
+        /// least as many as `b`, and the absolute difference.
+        pub fn balance_difference(&self, a: Address, b: Address) -> Result<(bool, U256), Vec<u8>> {
+            let balance_a = self.effective_balance(a);
+            let balance_b = self.effective_balance(b);
+            Ok(if balance_a >= balance_b {
+                (true, balance_a - balance_b)
+            } else {
+                (false, balance_b - balance_a)
+            })
+        }
+
+        /// `user`'s default-flavor balance as a share of the total supply across all flavors,
+        /// in basis points rounded down. Zero while nothing is in circulation.
+        pub fn ownership_share_bps(&self, user: Address) -> Result<U256, Vec<u8>> {
+            let supply = self.total_supply.get();
+            if supply.is_zero() {
+                return Ok(U256::ZERO);
+            }
+            Ok(self
+                .effective_balance(user)
+                .checked_mul(U256::from(FEE_DENOMINATOR))
+                .ok_or(VendingMachineError::Overflow(Overflow {}))?
+                / supply)
+        }
+
+        /// Returns the caller's own default-flavor cupcake balance.
+        pub fn my_balance(&self) -> Result<U256, Vec<u8>> {
+            Ok(self.effective_balance(self.vm().msg_sender()))
+        }
+
+        /// Moves `amount` cupcakes from the caller to `to`. Transfers and allowances only
+        /// cover the default flavor.
+        ///
+        /// If a transfer fee is configured, `to` receives `amount` minus the fee and the
+        /// fee is burned from the caller.
+        pub fn transfer_cupcake(&mut self, to: Address, amount: U256) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let from = self.vm().msg_sender();
+            self.transfer_with_fee(from, to, amount)?;
+            Ok(true)
+        }
+
+        /// ERC677-style transfer: moves `amount` cupcakes to `to` like `transfer_cupcake`, then
+        /// calls `onTokenTransfer(from, received, data)` on it, where `received` is net of
+        /// the transfer fee. Reverts the whole transfer if the hook fails.
+        pub fn transfer_and_call(
+            &mut self,
+            to: Address,
+            amount: U256,
+            data: Vec<u8>,
+        ) -> Result<bool, Vec<u8>> {
+            let from = self.vm().msg_sender();
+            let received = amount - self.transfer_fee_for(amount);
+            self.transfer_cupcake(to, amount)?;
+
+            let calldata = IERC677Receiver::onTokenTransferCall {
+                from,
+                amount: received,
+                data: data.into(),
+            }
+            .abi_encode();
+            self.vm().call(&Call::new(), to, &calldata).map_err(|_| {
+                VendingMachineError::TransferCallbackFailed(TransferCallbackFailed { receiver: to })
+            })?;
+            Ok(true)
+        }
+
+        pub fn claim_fee_bps(&self) -> U256 {
+            self.claim_fee_bps.get()
+        }
+
+        /// Fee burned from the sender of a transfer of `amount`. Never more than `amount`
+        /// itself.
+        pub fn transfer_fee_for(&self, amount: U256) -> U256 {
+            let fee_bps = self.transfer_fee_bps.get();
+            if fee_bps.is_zero() {
+                return U256::ZERO;
+            }
+            let denominator = U256::from(FEE_DENOMINATOR);
+            let scaled = amount.saturating_mul(fee_bps);
+            let mut fee = scaled / denominator;
+            if self.fee_round_half_up.get() && scaled % denominator >= denominator / U256::from(2) {
+                fee += U256::from(1);
+            }
+            fee.max(self.min_transfer_fee.get()).min(amount)
+        }
+
+        pub fn allowance(&self, owner: Address, spender: Address) -> U256 {
+            self.allowances.getter(owner).get(spender)
+        }
+
+        /// Lets `spender` move up to `value` of the caller's cupcakes.
+        pub fn approve(&mut self, spender: Address, value: U256) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let owner = self.vm().msg_sender();
+            self.approve_internal(owner, spender, value);
+            Ok(true)
+        }
+
+        pub fn increase_allowance(
+            &mut self,
+            spender: Address,
+            added: U256,
+        ) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let owner = self.vm().msg_sender();
+            let value = self.allowance(owner, spender).saturating_add(added);
+            self.approve_internal(owner, spender, value);
+            Ok(true)
+        }
+
+        pub fn decrease_allowance(
+            &mut self,
+            spender: Address,
+            subtracted: U256,
+        ) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let owner = self.vm().msg_sender();
+            let allowance = self.allowance(owner, spender);
+            if allowance < subtracted {
+                return Err(
+                    VendingMachineError::InsufficientAllowance(InsufficientAllowance {
+                        spender,
+                        allowance,
+                        needed: subtracted,
+                    })
+                    .into(),
+                );
+            }
+            self.approve_internal(owner, spender, allowance - subtracted);
+            Ok(true)
+        }
+
+        /// Current EIP-2612 permit nonce of `owner`.
+        pub fn nonces(&self, owner: Address) -> U256 {
+            self.nonces.get(owner)
+        }
+
+        /// Sets `spender`'s allowance from an EIP-2612 signature by `owner`, so the owner
+        /// doesn't need to send a transaction. Each signature is bound to the owner's current
+        /// nonce and can only be used once.
+        #[allow(clippy::too_many_arguments)]
+        pub fn permit(
+            &mut self,
+            owner: Address,
+            spender: Address,
+            value: U256,
+            deadline: U256,
+            v: u8,
+            r: B256,
+            s: B256,
+        ) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            if U256::from(self.vm().block_timestamp()) > deadline {
+                return Err(
+                    VendingMachineError::SignatureExpired(SignatureExpired { deadline }).into(),
+                );
+            }
+
+            let nonce = self.nonces.get(owner);
+            let permit = Permit {
+                owner,
+                spender,
+                value,
+                nonce,
+                deadline,
+            };
+            let digest = keccak(
+                [
+                    [0x19, 0x01].as_slice(),
+                    self.domain_separator().as_slice(),
+                    permit.eip712_hash_struct().as_slice(),
+                ]
+                .concat(),
+            );
+            if owner.is_zero() || self.recover_signer(digest, v, r, s) != Some(owner) {
+                return Err(VendingMachineError::InvalidSignature(InvalidSignature {}).into());
+            }
+
+            self.nonces.setter(owner).set(nonce + U256::from(1));
+            self.approve_internal(owner, spender, value);
+            Ok(())
+        }
+
+        /// Moves `amount` cupcakes from `from` to `to` using the caller's allowance. The
+        /// whole `amount` is spent from the allowance; `to` receives it net of the fee.
+        pub fn transfer_from(
+            &mut self,
+            from: Address,
+            to: Address,
+            amount: U256,
+        ) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let spender = self.vm().msg_sender();
+            self.spend_allowance(from, spender, amount)?;
+            self.transfer_with_fee(from, to, amount)?;
+            Ok(true)
+        }
+
+        /// Adds the attached ETH to the caller's stake.
+        #[payable]
+        pub fn stake(&mut self) -> Result<U256, Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            let stake = self
+                .stakes
+                .get(sender)
+                .checked_add(self.vm().msg_value())
+                .ok_or(VendingMachineError::Overflow(Overflow {}))?;
+            self.stakes.setter(sender).set(stake);
+            Ok(stake)
+        }
+
+        /// Sends `amount` wei of the caller's stake back to them. Dropping below
+        /// `required_stake` stops them from claiming. Works while paused so funds are never
+        /// stuck.
+        pub fn unstake(&mut self, amount: U256) -> Result<U256, Vec<u8>> {
+            let sender = self.vm().msg_sender();
+            let stake = self.stakes.get(sender);
+            if stake < amount {
+                return Err(VendingMachineError::InsufficientStake(InsufficientStake {
+                    user: sender,
+                    stake,
+                    needed: amount,
+                })
+                .into());
+            }
+            self.stakes.setter(sender).set(stake - amount);
+            self.send_eth(sender, amount)?;
+            Ok(stake - amount)
+        }
+
+        pub fn stake_of(&self, user: Address) -> U256 {
+            self.stakes.get(user)
+        }
+
+        /// Pays `cupcake_price` wei for a default-flavor claim by the caller. The claim goes
+        /// through `give_cupcake_to`, so the cooldown, caps and every other eligibility
+        /// check apply just as they do to a free one. Any change is refunded. Underpaying
+        /// reverts with `Underpaid` under `strict_payment`; otherwise the whole payment is
+        /// refunded and nothing is granted. A claim turned away with `false` refunds the
+        /// whole payment as well.
+        #[payable]
+        pub fn buy_cupcake(&mut self) -> Result<bool, Vec<u8>> {
+            self.when_not_paused()?;
+            let price = self.cupcake_price.get();
+            if price.is_zero() {
+                return Err(VendingMachineError::NotForSale(NotForSale {}).into());
+            }
+            let sender = self.vm().msg_sender();
+            let sent = self.vm().msg_value();
+            if sent < price {
+                if self.strict_payment.get() {
+                    return Err(VendingMachineError::Underpaid(Underpaid { sent, price }).into());
+                }
+                self.send_eth(sender, sent)?;
+                return Ok(false);
+            }
+            if !self.give_cupcake_to(sender, DEFAULT_FLAVOR)? {
+                self.send_eth(sender, sent)?;
+                return Ok(false);
+            }
+            self.sale_proceeds.set(self.sale_proceeds.get() + price);
+            if sent > price {
+                self.send_eth(sender, sent - price)?;
+            }
+            Ok(true)
+        }
+
+        pub fn cupcake_price(&self) -> U256 {
+            self.cupcake_price.get()
+        }
+
+        /// Wei earned by `buy_cupcake` and not yet withdrawn.
+        pub fn sale_proceeds(&self) -> U256 {
+            self.sale_proceeds.get()
+        }
+
+        /// Burns `amount` of the caller's default-flavor cupcakes. If `burns_restore_quota` is
+        /// set, they also come off the caller's lifetime count.
+        pub fn eat_cupcake(&mut self, amount: U256) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            self.burn(sender, DEFAULT_FLAVOR, amount, BALANCE_REASON_BURN)?;
+            if self.burns_restore_quota.get() {
+                let received = self.lifetime_received.get(sender);
+                self.lifetime_received
+                    .setter(sender)
+                    .set(received.saturating_sub(amount));
+            }
+            let burned = self.burned.get(sender) + amount;
+            self.burned.setter(sender).set(burned);
+            self.total_burned.set(self.total_burned.get() + amount);
+            log(
+                self.vm(),
+                Transfer {
+                    from: sender,
+                    to: Address::ZERO,
+                    value: amount,
+                },
+            );
+            Ok(())
+        }
+
+        /// Cupcakes `user` has received from claims, net of any burns that restored quota.
+        pub fn lifetime_received(&self, user: Address) -> U256 {
+            self.lifetime_received.get(user)
+        }
+
+        /// Cupcakes `user` has eaten through `eat_cupcake`. Fees and decay aren't counted.
+        pub fn burned_by(&self, user: Address) -> U256 {
+            self.burned.get(user)
+        }
+
+        /// Cupcakes eaten through `eat_cupcake` by everyone.
+        pub fn total_burned_global(&self) -> U256 {
+            self.total_burned.get()
+        }
+
+        /// Barters cupcakes with `counterparty`: `my_amount` moves from the caller to them and
+        /// `their_amount` comes back, paid out of the allowance they gave the caller. Both
+        /// sides are checked before anything moves, and each leg pays its own transfer fee.
+        pub fn swap(
+            &mut self,
+            counterparty: Address,
+            my_amount: U256,
+            their_amount: U256,
+        ) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            let sender = self.vm().msg_sender();
+            self.ensure_balance(sender, my_amount)?;
+            self.ensure_balance(counterparty, their_amount)?;
+            self.spend_allowance(counterparty, sender, their_amount)?;
+            self.transfer_with_fee(sender, counterparty, my_amount)?;
+            self.transfer_with_fee(counterparty, sender, their_amount)?;
+            Ok(())
+        }
+
+        /// Transfers `amounts[i]` cupcakes from the caller to `recipients[i]`.
+        ///
+        /// The whole batch is checked against the caller's balance before anything moves,
+        /// so either every transfer happens or none do. Each transfer pays its own fee.
+        pub fn batch_transfer(
+            &mut self,
+            recipients: Vec<Address>,
+            amounts: Vec<U256>,
+        ) -> Result<(), Vec<u8>> {
+            self.when_not_paused()?;
+            if recipients.len() != amounts.len() {
+                return Err(VendingMachineError::LengthMismatch(LengthMismatch {}).into());
+            }
+            self.check_batch_size(recipients.len())?;
+
+            let from = self.vm().msg_sender();
+            let balance = self.effective_balance(from);
+            let mut needed = U256::ZERO;
+            for amount in &amounts {
+                needed = needed.saturating_add(*amount);
+                if needed > balance {
+                    return Err(
+                        VendingMachineError::InsufficientBalance(InsufficientBalance {
+                            from,
+                            balance,
+                            needed,
+                        })
+                        .into(),
+                    );
+                }
+            }
+
+            for (to, amount) in recipients.into_iter().zip(amounts) {
+                self.transfer_with_fee(from, to, amount)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The core vending behavior, for Rust code that wants to depend on it abstractly or
+/// swap in a fake in tests. Everything applies to the default flavor.
+pub trait CupcakeDispenser {
+    /// Gives `user` a cupcake, returning `Ok(false)` if they are still cooling down.
+    fn give(&mut self, user: Address) -> Result<bool, Vec<u8>>;
+
+    /// The number of cupcakes `user` holds.
+    fn balance_of(&self, user: Address) -> U256;
+
+    /// Whether `user` could receive a cupcake right now.
+    fn can_receive(&self, user: Address) -> bool;
+}
+
+impl CupcakeDispenser for VendingMachine {
+    fn give(&mut self, user: Address) -> Result<bool, Vec<u8>> {
+        self.give_cupcake_to(user, DEFAULT_FLAVOR)
+    }
+
+    fn balance_of(&self, user: Address) -> U256 {
+        self.effective_balance(user)
+    }
+
+    fn can_receive(&self, user: Address) -> bool {
+        self.cooldown_remaining(user, DEFAULT_FLAVOR).is_zero()
+    }
+}
+
+/// Extension points for forks. Stylus contracts can't be subclassed, so instead of
+/// overriding a virtual method a fork fills in the `impl GrantHooks for VendingMachine`
+/// block below and leaves the core logic alone.
+trait GrantHooks {
+    /// Runs at the end of every successful grant, after the reward token payout, with the
+    /// number of cupcakes `user` just received. Does nothing by default.
+    fn on_cupcake_given(&mut self, _user: Address, _amount: U256) {}
+}
+
+#[cfg(not(test))]
+impl GrantHooks for VendingMachine {}
+
+/// Length of one cooldown `unit` in seconds, `None` for an unknown unit.
+fn unit_seconds(unit: u8) -> Option<u64> {
+    match unit {
+        COOLDOWN_UNIT_SECONDS => Some(1),
+        COOLDOWN_UNIT_MINUTES => Some(60),
+        COOLDOWN_UNIT_HOURS => Some(3_600),
+        _ => None,
+    }
+}
+
+/// Log line for a claim turned away by the cooldown. Unused when `console!` compiles to
+/// nothing, i.e. without the `debug` feature.
+#[cfg_attr(not(feature = "debug"), allow(dead_code))]
+fn cooldown_message(remaining: U256) -> alloc::string::String {
+    let unit = if remaining == U256::from(1) {
+        "second"
+    } else {
+        "seconds"
+    };
+    alloc::format!(
+        "HTTP 429: Too Many Cupcakes (you must wait {remaining} more {unit} before your next cupcake)"
+    )
+}
+
+impl VendingMachine {
+    /// Merkle allowlist leaf for `user`.
+    fn merkle_leaf(user: Address) -> B256 {
+        keccak(user)
+    }
+
+    /// Hashes two merkle nodes in sorted order, so proofs don't need to encode sides.
+    fn merkle_pair(a: B256, b: B256) -> B256 {
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        keccak([low.as_slice(), high.as_slice()].concat())
+    }
+
+    /// `keccak256(prev_root ++ timestamp ++ amount)`, each a 32-byte big-endian word, so
+    /// off-chain systems can replay a user's claims and compare against `get_history_root`.
+    fn next_history_root(prev_root: B256, timestamp: U256, amount: U256) -> B256 {
+        keccak(
+            [
+                prev_root.as_slice(),
+                &timestamp.to_be_bytes::<32>(),
+                &amount.to_be_bytes::<32>(),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Folds `proof` into `leaf` to get the root it commits to.
+    fn merkle_root_from(leaf: B256, proof: &[B256]) -> B256 {
+        proof
+            .iter()
+            .fold(leaf, |node, sibling| Self::merkle_pair(node, *sibling))
+    }
+
+    #[cfg(not(feature = "no-admin"))]
+    fn ensure_owner(&self) -> Result<(), Vec<u8>> {
+        let caller = self.vm().msg_sender();
+        if caller != self.owner.get() {
+            return Err(VendingMachineError::NotOwner(NotOwner { caller }).into());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "no-admin"))]
+    fn only_owner(&self) -> Result<(), Vec<u8>> {
+        self.ensure_owner()?;
+        if !self.is_admin_active() {
+            return Err(VendingMachineError::AdminExpired(AdminExpired {
+                lastSeen: self.owner_last_seen.get(),
+            })
+            .into());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "no-admin"))]
+    fn ensure_config_not_frozen(&self) -> Result<(), Vec<u8>> {
+        if self.config_frozen.get() {
+            return Err(VendingMachineError::ConfigFrozen(ConfigFrozen {}).into());
+        }
+        Ok(())
+    }
+
+    /// `only_owner` for state-changing admin methods: also counts the action towards
+    /// `get_admin_action_count` and resets the dead-man switch.
+    #[cfg(not(feature = "no-admin"))]
+    fn only_owner_action(&mut self) -> Result<(), Vec<u8>> {
+        self.only_owner()?;
+        self.admin_action_count
+            .set(self.admin_action_count.get() + U256::from(1));
+        self.owner_last_seen
+            .set(U256::from(self.vm().block_timestamp()));
+        Ok(())
+    }
+
+    /// `only_owner_action` for methods that only resume the contract or release funds.
+    /// These stay open to the owner after the dead-man switch fires, but then no longer
+    /// reset it.
+    #[cfg(not(feature = "no-admin"))]
+    fn only_owner_recovery(&mut self) -> Result<(), Vec<u8>> {
+        if self.is_admin_active() {
+            return self.only_owner_action();
+        }
+        self.ensure_owner()?;
+        self.admin_action_count
+            .set(self.admin_action_count.get() + U256::from(1));
+        Ok(())
+    }
+
+    /// `only_owner_action` for config setters: also rejects the change once the config is
+    /// frozen or if the previous one was less than `admin_cooldown` ago, then records
+    /// this one. Moderation and emergency
+    /// actions such as `pause` stay on `only_owner_action`.
+    #[cfg(not(feature = "no-admin"))]
+    fn only_owner_config_change(&mut self) -> Result<(), Vec<u8>> {
+        self.only_owner()?;
+        self.ensure_config_not_frozen()?;
+        let now = U256::from(self.vm().block_timestamp());
+        let last_change = self.last_config_change.get();
+        let next_allowed = last_change.saturating_add(self.admin_cooldown.get());
+        if !last_change.is_zero() && now < next_allowed {
+            return Err(
+                VendingMachineError::ConfigChangeTooSoon(ConfigChangeTooSoon {
+                    nextAllowed: next_allowed,
+                })
+                .into(),
+            );
+        }
+        self.last_config_change.set(now);
+        self.owner_last_seen.set(now);
+        self.admin_action_count
+            .set(self.admin_action_count.get() + U256::from(1));
+        Ok(())
+    }
+
+    fn eip712_domain(&self) -> Eip712Domain {
+        Eip712Domain::new(
+            Some(EIP712_NAME.into()),
+            Some(EIP712_VERSION.into()),
+            Some(U256::from(self.vm().chain_id())),
+            Some(self.vm().contract_address()),
+            None,
+        )
+    }
+
+    #[cfg(not(feature = "no-admin"))]
+    fn cache_domain_separator(&mut self) {
+        let separator = self.eip712_domain().separator();
+        let chain_id = U256::from(self.vm().chain_id());
+        self.cached_domain_separator.set(separator);
+        self.cached_chain_id.set(chain_id);
+    }
+
+    /// Recovers the signer of `digest` through the `ecrecover` precompile.
+    fn recover_signer(&self, digest: B256, v: u8, r: B256, s: B256) -> Option<Address> {
+        let mut input = Vec::with_capacity(128);
+        input.extend_from_slice(digest.as_slice());
+        input.extend_from_slice(&B256::from(U256::from(v))[..]);
+        input.extend_from_slice(r.as_slice());
+        input.extend_from_slice(s.as_slice());
+
+        let returned = self
+            .vm()
+            .static_call(&Call::new(), ECRECOVER, &input)
+            .ok()?;
+        if returned.len() != 32 {
+            return None;
+        }
+        Some(Address::from_slice(&returned[12..]))
+    }
+
+    /// Guard for every state-changing method that isn't owner configuration.
+    fn when_not_paused(&self) -> Result<(), Vec<u8>> {
+        if self.paused.get() {
+            return Err(VendingMachineError::ContractPaused(ContractPaused {}).into());
+        }
+        Ok(())
+    }
+
+    /// Records a grant in the `rate_limit`-slot ring buffer of recent grant times, or pauses
+    /// the contract and returns `true` if the oldest of them is still inside `rate_window`.
+    fn trip_circuit_breaker(&mut self) -> bool {
+        let limit = self.rate_limit.get();
+        if limit.is_zero() {
+            return false;
+        }
+        let now = U256::from(self.vm().block_timestamp());
+        let next = self.recent_grants_next.get();
+        if U256::from(self.recent_grants.len()) < limit {
+            self.recent_grants.push(now);
+        } else {
+            // Once the buffer is full, `next` points at the oldest grant.
+            let oldest = self.recent_grants.get(next).unwrap_or_default();
+            let window = self.rate_window.get();
+            if now.saturating_sub(oldest) < window {
+                self.paused.set(true);
+                log(
+                    self.vm(),
+                    CircuitBreakerTripped {
+                        rateLimit: limit,
+                        rateWindow: window,
+                        timestamp: now,
+                    },
+                );
+                return true;
+            }
+            if let Some(mut slot) = self.recent_grants.setter(next) {
+                slot.set(now);
+            }
+        }
+        self.recent_grants_next.set((next + U256::from(1)) % limit);
+        false
+    }
+
+    #[cfg(not(feature = "no-admin"))]
+    fn reset_recent_grants(&mut self) {
+        self.recent_grants.truncate(0);
+        self.recent_grants_next.set(U256::ZERO);
+    }
+
+    fn ensure_not_blacklisted(&self, user: Address) -> Result<(), Vec<u8>> {
+        if self.blacklisted.get(user) {
+            return Err(VendingMachineError::Blacklisted(Blacklisted { user }).into());
+        }
+        Ok(())
+    }
+
+    fn check_batch_size(&self, length: usize) -> Result<(), Vec<u8>> {
+        let max = self.max_batch_size.get();
+        let length = U256::from(length);
+        if !max.is_zero() && length > max {
+            return Err(VendingMachineError::BatchTooLarge(BatchTooLarge { length, max }).into());
+        }
+        Ok(())
+    }
+
+    fn ensure_not_frozen(&self, user: Address) -> Result<(), Vec<u8>> {
+        if self.frozen.get(user) {
+            return Err(VendingMachineError::AccountFrozen(AccountFrozen { user }).into());
+        }
+        Ok(())
+    }
+
+    /// Claims recorded in the current block. The counter belongs to whichever block last
+    /// claimed, so it reads as zero once a new block starts.
+    fn claims_this_block(&self) -> U256 {
+        if self.claims_block.get() == U256::from(self.vm().block_number()) {
+            self.claims_in_block.get()
+        } else {
+            U256::ZERO
+        }
+    }
+
+    fn ensure_staked(&self, user: Address) -> Result<(), Vec<u8>> {
+        let stake = self.stakes.get(user);
+        let required = self.required_stake.get();
+        if stake < required {
+            return Err(VendingMachineError::StakeTooLow(StakeTooLow {
+                user,
+                stake,
+                required,
+            })
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Whether a default-flavor claim for `user` would go through, by the same checks as
+    /// `try_give`.
+    fn is_eligible(&self, user: Address) -> bool {
+        matches!(self.reject_reason(user), Ok(REASON_OK))
+    }
+
+    /// Seconds since the user's last distribution. Saturates to zero if the stored
+    /// time is ahead of the current block, e.g. after importing state.
+    fn elapsed_since(&self, user: Address, flavor: U256) -> U256 {
+        let last_distribution = self.cupcake_distribution_times.getter(flavor).get(user);
+        U256::from(self.vm().block_timestamp()).saturating_sub(last_distribution)
+    }
+
+    /// Seconds the user still has to wait, taking any global waiver or the launch
+    /// grace period into account.
+    fn cooldown_remaining(&self, user: Address, flavor: U256) -> U256 {
+        if self.is_cooldown_waived() || self.in_grace_period() {
+            return U256::ZERO;
+        }
+        self.effective_cooldown(user, flavor)
+            .saturating_sub(self.elapsed_since(user, flavor))
+    }
+
+    fn base_cooldown_seconds(&self) -> U256 {
+        if self.has_base_cooldown.get() {
+            let unit = unit_seconds(self.cooldown_unit.get().to()).unwrap_or(1);
+            self.base_cooldown.get().saturating_mul(U256::from(unit))
+        } else {
+            U256::from(COOLDOWN_SECONDS)
+        }
+    }
+
+    /// Base cooldown plus `cooldown_step` per cupcake held, capped at `max_cooldown`, plus
+    /// the user's jitter.
+    fn effective_cooldown(&self, user: Address, flavor: U256) -> U256 {
+        if self.has_cooldown_override.get(user) {
+            return self.cooldown_overrides.get(user);
+        }
+        let cooldown = self
+            .base_cooldown_seconds()
+            .saturating_add(self.cooldown_growth(user, flavor));
+        let max_cooldown = self.max_cooldown.get();
+        let capped = if max_cooldown.is_zero() {
+            cooldown
+        } else {
+            cooldown.min(max_cooldown)
+        };
+        capped.saturating_add(self.cooldown_jitter(user))
+    }
+
+    /// The balance-scaled part of `user`'s cooldown: `cooldown_step` per cupcake held.
+    fn cooldown_growth(&self, user: Address, flavor: U256) -> U256 {
+        self.flavor_balance(user, flavor)
+            .saturating_mul(self.cooldown_step.get())
+    }
+
+    /// Fixed offset in `[0, jitter_range)` derived from `user`'s address.
+    fn cooldown_jitter(&self, user: Address) -> U256 {
+        let range = self.jitter_range.get();
+        if range.is_zero() {
+            return U256::ZERO;
+        }
+        U256::from_be_bytes(keccak(user).0) % range
+    }
+
+    /// Records the user's first sighting and checks it against `min_account_age`.
+    ///
+    /// A first sighting returns `Ok(false)` instead of reverting when an age is required,
+    /// since a revert would also roll back the timestamp we just recorded. Later claims
+    /// made before the age is reached revert with `AccountTooNew`.
+    fn check_account_age(&mut self, user: Address) -> Result<bool, Vec<u8>> {
+        let now = U256::from(self.vm().block_timestamp());
+        let min_age = self.min_account_age.get();
+        let first_seen = self.first_seen.get(user);
+        if first_seen.is_zero() {
+            self.first_seen.setter(user).set(now);
+            return Ok(min_age.is_zero());
+        }
+
+        let eligible_at = first_seen.saturating_add(min_age);
+        if now < eligible_at {
+            return Err(VendingMachineError::AccountTooNew(AccountTooNew {
+                user,
+                eligibleAt: eligible_at,
+            })
+            .into());
+        }
+        Ok(true)
+    }
+
+    /// Mints the caller's keeper reward for `grants` grants, never past the supply cap.
+    /// Blacklisted callers get nothing.
+    fn pay_keeper(&mut self, grants: U256) -> Result<(), Vec<u8>> {
+        let keeper = self.vm().msg_sender();
+        let mut reward = self
+            .to_base_units(self.keeper_reward.get())
+            .saturating_mul(grants);
+        let max_supply = self.max_supply.get();
+        if !max_supply.is_zero() {
+            reward = reward.min(max_supply.saturating_sub(self.total_distributed.get()));
+        }
+        if reward.is_zero() || self.blacklisted.get(keeper) {
+            return Ok(());
+        }
+        self.mint(keeper, DEFAULT_FLAVOR, reward, BALANCE_REASON_GRANT)?;
+        let distributed = self.total_distributed.get() + reward;
+        self.total_distributed.set(distributed);
+        Ok(())
+    }
+
+    /// What a successful claim of `flavor` grants `user`, all of it checked against the
+    /// caps and counted as distributed: the current grant plus any first-claim bonus and,
+    /// for the default flavor, the auto-compound bonus.
+    fn claim_grant_amount(&self, user: Address, flavor: U256) -> Result<U256, Vec<u8>> {
+        let mut bonus = U256::ZERO;
+        if self.would_get_first_bonus(user) {
+            bonus = self.to_base_units(self.first_claim_bonus.get());
+        }
+        if flavor == DEFAULT_FLAVOR {
+            bonus = bonus.saturating_add(self.auto_compound_bonus(user));
+        }
+        Ok(self
+            .current_grant_amount()
+            .checked_add(bonus)
+            .ok_or(VendingMachineError::Overflow(Overflow {}))?)
+    }
+
+    /// Bonus an auto-compounding `user` would be minted by a default-flavor claim right
+    /// now: their streak after that claim. Zero when they haven't opted in.
+    fn auto_compound_bonus(&self, user: Address) -> U256 {
+        if !self.auto_compound.get(user) {
+            return U256::ZERO;
+        }
+        self.to_base_units(self.streak_after_claim(user, U256::from(self.vm().block_timestamp())))
+    }
+
+    /// Scales a whole-cupcake amount by `10^precision`. Grants and every bonus go through
+    /// this so they stay in the same unit.
+    fn to_base_units(&self, units: U256) -> U256 {
+        units.saturating_mul(U256::from(10).pow(U256::from(self.precision.get())))
+    }
+
+    /// Whether granting `amount` more cupcakes would go past the supply cap.
+    fn exceeds_supply_cap(&self, amount: U256) -> bool {
+        let max_supply = self.max_supply.get();
+        !max_supply.is_zero() && self.total_distributed.get().saturating_add(amount) > max_supply
+    }
+
+    /// Maintenance fee the next claim of `flavor` would burn from `user`'s balance.
+    fn claim_fee_for(&self, user: Address, flavor: U256) -> U256 {
+        self.effective_flavor_balance(user, flavor)
+            .saturating_mul(self.claim_fee_bps.get())
+            / U256::from(FEE_DENOMINATOR)
+    }
+
+    /// Maps the zero address to the caller when the fallback is enabled, and rejects it
+    /// otherwise.
+    fn resolve_recipient(&self, user: Address) -> Result<Address, Vec<u8>> {
+        if !user.is_zero() {
+            return Ok(user);
+        }
+        if !self.zero_address_fallback.get() {
+            return Err(VendingMachineError::ZeroRecipient(ZeroRecipient {}).into());
+        }
+        Ok(self.vm().msg_sender())
+    }
+
+    /// `resolve_recipient` for every entry of a batch, so eligibility is checked against
+    /// the address that will actually receive the cupcake.
+    fn resolve_recipients(&self, users: Vec<Address>) -> Result<Vec<Address>, Vec<u8>> {
+        users
+            .into_iter()
+            .map(|user| self.resolve_recipient(user))
+            .collect()
+    }
+
+    fn register_flavor(&mut self, flavor: U256) {
+        if !self.flavor_registered.get(flavor) {
+            self.flavor_registered.setter(flavor).set(true);
+            self.flavor_ids.push(flavor);
+        }
+    }
+
+    fn exceeds_lifetime_cap(&self, user: Address, amount: U256) -> bool {
+        let cap = self.lifetime_cap.get();
+        !cap.is_zero() && self.lifetime_received.get(user).saturating_add(amount) > cap
+    }
+
+    fn exceeds_flavor_cap(&self, flavor: U256, amount: U256) -> bool {
+        amount > self.flavor_remaining(flavor)
+    }
+
+    /// Mirrors the checks in `give_cupcake_to`, in the same order, without writing
+    /// anything. Returns `REASON_OK` if a default-flavor claim would go through, short of
+    /// the circuit breaker, which only trips as the grant is recorded.
+    fn reject_reason(&self, user: Address) -> Result<u8, Vec<u8>> {
+        if self.paused.get() {
+            return Ok(REASON_PAUSED);
+        }
+        if self.blacklisted.get(user) {
+            return Ok(REASON_BLACKLISTED);
+        }
+        if self.frozen.get(user) {
+            return Ok(REASON_FROZEN);
+        }
+        if self.stakes.get(user) < self.required_stake.get() {
+            return Ok(REASON_STAKE_TOO_LOW);
+        }
+        if self.block_contracts.get() && self.vm().code_size(user) > 0 {
+            return Ok(REASON_CONTRACT);
+        }
+        let min_age = self.min_account_age.get();
+        let first_seen = self.first_seen.get(user);
+        let too_new = if first_seen.is_zero() {
+            !min_age.is_zero()
+        } else {
+            U256::from(self.vm().block_timestamp()) < first_seen.saturating_add(min_age)
+        };
+        if too_new {
+            return Ok(REASON_TOO_NEW);
+        }
+        if !self.cooldown_remaining(user, DEFAULT_FLAVOR).is_zero() {
+            return Ok(REASON_COOLDOWN);
+        }
+        let amount = self.claim_grant_amount(user, DEFAULT_FLAVOR)?;
+        if self.exceeds_supply_cap(amount) || self.exceeds_flavor_cap(DEFAULT_FLAVOR, amount) {
+            return Ok(REASON_SOLD_OUT);
+        }
+        if self.exceeds_lifetime_cap(user, amount) {
+            return Ok(REASON_LIFETIME_CAP);
+        }
+        let max_per_block = self.max_claims_per_block.get();
+        if !max_per_block.is_zero() && self.claims_this_block() >= max_per_block {
+            return Ok(REASON_BLOCK_LIMIT);
+        }
+        let limit = self.daily_limit.get();
+        if !limit.is_zero() && self.claims_today(user) >= limit {
+            return Ok(REASON_DAILY_LIMIT);
+        }
+        Ok(REASON_OK)
+    }
+
+    /// Records the first sighting of a user turned away as too new before they were ever
+    /// seen, as `give_cupcake_to` would, so the minimum age starts counting.
+    fn note_first_sighting(&mut self, user: Address, reason: u8) -> Result<(), Vec<u8>> {
+        if reason == REASON_TOO_NEW && self.first_seen.get(user).is_zero() {
+            self.check_account_age(user)?;
+        }
+        Ok(())
+    }
+
+    fn in_grace_period(&self) -> bool {
+        let grace_end = self
+            .start_time
+            .get()
+            .saturating_add(self.grace_period.get());
+        U256::from(self.vm().block_timestamp()) < grace_end
+    }
+
+    /// Claims the user has made in the current day bucket.
+    fn claims_today(&self, user: Address) -> U256 {
+        let today = U256::from(self.vm().block_timestamp() / SECONDS_PER_DAY);
+        if self.claim_days.get(user) == today {
+            self.daily_claims.get(user)
+        } else {
+            U256::ZERO
+        }
+    }
+
+    /// Counts a claim in the user's current day bucket, resetting the count when
+    /// the day rolls over.
+    fn record_daily_claim(&mut self, user: Address) -> Result<(), Vec<u8>> {
+        let limit = self.daily_limit.get();
+        if limit.is_zero() {
+            return Ok(());
+        }
+
+        let today = U256::from(self.vm().block_timestamp() / SECONDS_PER_DAY);
+        let claims = self.claims_today(user);
+        if claims >= limit {
+            return Err(
+                VendingMachineError::DailyLimitReached(DailyLimitReached { user, limit }).into(),
+            );
+        }
+
+        self.claim_days.setter(user).set(today);
+        self.daily_claims.setter(user).set(claims + U256::from(1));
+        Ok(())
+    }
+
+    fn flavor_balance(&self, user: Address, flavor: U256) -> U256 {
+        self.cupcake_balances.getter(flavor).get(user)
+    }
+
+    /// Stored balance minus pending decay. Only the default flavor decays.
+    fn effective_flavor_balance(&self, user: Address, flavor: U256) -> U256 {
+        let balance = self.flavor_balance(user, flavor);
+        balance - self.pending_decay(user, flavor, balance)
+    }
+
+    fn pending_decay(&self, user: Address, flavor: U256, balance: U256) -> U256 {
+        if flavor != DEFAULT_FLAVOR || !self.decay_enabled.get() {
+            return U256::ZERO;
+        }
+        let since = self.last_activity.get(user).max(self.decay_since.get());
+        let elapsed = U256::from(self.vm().block_timestamp()).saturating_sub(since);
+        elapsed
+            .saturating_mul(self.decay_per_second.get())
+            .min(balance)
+    }
+
+    /// Writes pending decay to storage as a burn and restarts the user's decay clock.
+    /// Runs before every balance change so writes start from the decayed balance.
+    fn materialize_decay(&mut self, user: Address, flavor: U256) {
+        if flavor != DEFAULT_FLAVOR || !self.decay_enabled.get() {
+            return;
+        }
+        let now = U256::from(self.vm().block_timestamp());
+        let balance = self.flavor_balance(user, flavor);
+        let decayed = self.pending_decay(user, flavor, balance);
+        if !decayed.is_zero() {
+            self.set_flavor_balance(user, flavor, balance - decayed, BALANCE_REASON_DECAY);
+            let supply = self.total_supply.get();
+            self.set_total_supply(supply.saturating_sub(decayed));
+            let flavor_supply = self.flavor_total_supply.get(flavor);
+            self.flavor_total_supply
+                .setter(flavor)
+                .set(flavor_supply.saturating_sub(decayed));
+            log(
+                self.vm(),
+                Transfer {
+                    from: user,
+                    to: Address::ZERO,
+                    value: decayed,
+                },
+            );
+        }
+        self.last_activity.setter(user).set(now);
+    }
+
+    /// Writes `user`'s balance of `flavor`. Every balance change goes through here so the
+    /// holder count, which counts accounts holding any flavor, stays in step, and so
+    /// `BalanceChanged` is logged with `reason` when enabled.
+    fn set_flavor_balance(&mut self, user: Address, flavor: U256, balance: U256, reason: u8) {
+        let held = self.holdings.get(user);
+        let old_balance = self.flavor_balance(user, flavor);
+        let new_held = held - old_balance + balance;
+        if self.balance_events.get() && old_balance != balance {
+            log(
+                self.vm(),
+                BalanceChanged {
+                    user,
+                    flavorId: flavor,
+                    oldBalance: old_balance,
+                    newBalance: balance,
+                    reason,
+                },
+            );
+        }
+        if held.is_zero() && !new_held.is_zero() && !self.in_holder_list.get(user) {
+            self.in_holder_list.setter(user).set(true);
+            self.holder_list.push(user);
+        }
+        if held.is_zero() != new_held.is_zero() {
+            let holders = self.holder_count.get();
+            self.holder_count.set(if held.is_zero() {
+                holders + U256::from(1)
+            } else {
+                holders - U256::from(1)
+            });
+        }
+        self.holdings.setter(user).set(new_held);
+        self.cupcake_balances
+            .setter(flavor)
+            .setter(user)
+            .set(balance);
+    }
+
+    /// Writes the total supply and checkpoints it for `total_supply_at`. Several changes in
+    /// one block share a checkpoint holding the block's final value.
+    fn set_total_supply(&mut self, supply: U256) {
+        self.total_supply.set(supply);
+        let block = U256::from(self.vm().block_number());
+        let len = self.supply_checkpoint_blocks.len();
+        if len > 0 && self.supply_checkpoint_blocks.get(len - 1) == Some(block) {
+            if let Some(mut value) = self.supply_checkpoint_values.setter(len - 1) {
+                value.set(supply);
+            }
+        } else {
+            self.supply_checkpoint_blocks.push(block);
+            self.supply_checkpoint_values.push(supply);
+        }
+    }
+
+    /// Credits `amount` new cupcakes of `flavor` to `to` and grows the total supply,
+    /// which counts every flavor. Default-flavor mints log a `Transfer` from the zero
+    /// address; other flavors have no token to log against.
+    fn mint(&mut self, to: Address, flavor: U256, amount: U256, reason: u8) -> Result<(), Vec<u8>> {
+        if self.safe_mint.get() && self.vm().code_size(to) > 0 {
+            self.ensure_accepts_mint(to, flavor)?;
+        }
+        self.materialize_decay(to, flavor);
+        let overflow = || VendingMachineError::Overflow(Overflow {});
+        let supply = self
+            .total_supply
+            .get()
+            .checked_add(amount)
+            .ok_or_else(overflow)?;
+        let balance = self
+            .flavor_balance(to, flavor)
+            .checked_add(amount)
+            .ok_or_else(overflow)?;
+        let flavor_supply = self.flavor_total_supply.get(flavor) + amount;
+        self.set_flavor_balance(to, flavor, balance, reason);
+        self.set_total_supply(supply);
+        self.flavor_total_supply.setter(flavor).set(flavor_supply);
+        if flavor == DEFAULT_FLAVOR {
+            log(
+                self.vm(),
+                Transfer {
+                    from: Address::ZERO,
+                    to,
+                    value: amount,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Asks the contract at `to` to accept a mint of `flavor`, ERC721 style: the call must
+    /// succeed and return the `onERC721Received` selector.
+    fn ensure_accepts_mint(&self, to: Address, flavor: U256) -> Result<(), Vec<u8>> {
+        let calldata = IERC721Receiver::onERC721ReceivedCall {
+            operator: self.vm().msg_sender(),
+            from: Address::ZERO,
+            tokenId: flavor,
+            data: Vec::new().into(),
+        }
+        .abi_encode();
+        let accepted = self
+            .vm()
+            .call(&Call::new(), to, &calldata)
+            .ok()
+            .and_then(|returned| {
+                IERC721Receiver::onERC721ReceivedCall::abi_decode_returns(&returned, true).ok()
+            })
+            .is_some_and(|returned| returned._0 == IERC721Receiver::onERC721ReceivedCall::SELECTOR);
+        if !accepted {
+            return Err(VendingMachineError::UnsafeRecipient(UnsafeRecipient { to }).into());
+        }
+        Ok(())
+    }
+
+    fn send_eth(&mut self, to: Address, amount: U256) -> Result<(), Vec<u8>> {
+        self.vm().transfer_eth(to, amount).map_err(|_| {
+            VendingMachineError::EthTransferFailed(EthTransferFailed { to, amount })
+        })?;
+        Ok(())
+    }
+
+    /// Destroys `amount` of `from`'s cupcakes of `flavor` and shrinks the total supply.
+    fn burn(
+        &mut self,
+        from: Address,
+        flavor: U256,
+        amount: U256,
+        reason: u8,
+    ) -> Result<(), Vec<u8>> {
+        self.materialize_decay(from, flavor);
+        let balance = self.flavor_balance(from, flavor);
+        if balance < amount {
+            return Err(
+                VendingMachineError::InsufficientBalance(InsufficientBalance {
+                    from,
+                    balance,
+                    needed: amount,
+                })
+                .into(),
+            );
+        }
+        self.set_flavor_balance(from, flavor, balance - amount, reason);
+        let supply = self.total_supply.get();
+        self.set_total_supply(supply.saturating_sub(amount));
+        let flavor_supply = self.flavor_total_supply.get(flavor);
+        self.flavor_total_supply
+            .setter(flavor)
+            .set(flavor_supply.saturating_sub(amount));
+        Ok(())
+    }
+
+    /// Must run before the distribution time is overwritten with `now`.
+    fn update_streak(&mut self, user: Address, now: U256) {
+        let streak = self.streak_after_claim(user, now);
+        self.streaks.setter(user).set(streak);
+    }
+
+    /// The streak `user` would have after a default-flavor claim at `now`.
+    fn streak_after_claim(&self, user: Address, now: U256) -> U256 {
+        let last_distribution = self
+            .cupcake_distribution_times
+            .getter(DEFAULT_FLAVOR)
+            .get(user);
+        let window = self.streak_window.get();
+        let continues = !last_distribution.is_zero()
+            && (window.is_zero() || now.saturating_sub(last_distribution) <= window);
+        if continues {
+            self.streaks.get(user) + U256::from(1)
+        } else {
+            U256::from(1)
+        }
+    }
+
+    #[cfg(not(feature = "no-admin"))]
+    fn legacy_balance(&self, legacy: Address, user: Address) -> Result<U256, Vec<u8>> {
+        let calldata =
+            ILegacyVendingMachine::getCupcakeBalanceForCall { userAddress: user }.abi_encode();
+        let failed = || VendingMachineError::LegacyReadFailed(LegacyReadFailed { legacy, user });
+        let returned = self
+            .vm()
+            .static_call(&Call::new(), legacy, &calldata)
+            .map_err(|_| failed())?;
+        Ok(
+            ILegacyVendingMachine::getCupcakeBalanceForCall::abi_decode_returns(&returned, true)
+                .map_err(|_| failed())?
+                ._0,
+        )
+    }
+
+    /// Reads `account`'s balance of an external ERC20. A failed read is reported as a
+    /// failed transfer, since it only happens on the way to one.
+    #[cfg(not(feature = "no-admin"))]
+    fn erc20_balance_of(&self, token: Address, account: Address) -> Result<U256, Vec<u8>> {
+        let calldata = IERC20::balanceOfCall { account }.abi_encode();
+        let failed = || VendingMachineError::TokenTransferFailed(TokenTransferFailed { token });
+        let returned = self
+            .vm()
+            .static_call(&Call::new(), token, &calldata)
+            .map_err(|_| failed())?;
+        Ok(IERC20::balanceOfCall::abi_decode_returns(&returned, true)
+            .map_err(|_| failed())?
+            ._0)
+    }
+
+    /// Calls `transfer` on an external ERC20. Tokens that return no data are treated
+    /// as successful, matching the common safe-transfer convention.
+    fn erc20_transfer(&self, token: Address, to: Address, amount: U256) -> Result<(), Vec<u8>> {
+        let calldata = IERC20::transferCall { to, amount }.abi_encode();
+        let failed = || VendingMachineError::TokenTransferFailed(TokenTransferFailed { token });
+        let returned = self
+            .vm()
+            .call(&Call::new(), token, &calldata)
+            .map_err(|_| failed())?;
+        if !returned.is_empty() {
+            let ok = IERC20::transferCall::abi_decode_returns(&returned, true)
+                .map_err(|_| failed())?
+                ._0;
+            if !ok {
+                return Err(failed().into());
+            }
+        }
+        Ok(())
+    }
+
+    fn approve_internal(&mut self, owner: Address, spender: Address, value: U256) {
+        self.allowances.setter(owner).setter(spender).set(value);
+        log(
+            self.vm(),
+            Approval {
+                owner,
+                spender,
+                value,
+            },
+        );
+    }
+
+    fn ensure_balance(&self, from: Address, needed: U256) -> Result<(), Vec<u8>> {
+        let balance = self.effective_balance(from);
+        if balance < needed {
+            return Err(
+                VendingMachineError::InsufficientBalance(InsufficientBalance {
+                    from,
+                    balance,
+                    needed,
+                })
+                .into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// A `U256::MAX` allowance is treated as infinite and never decremented, as most
+    /// ERC20s do, which saves a storage write for integrations that approve the max.
+    fn spend_allowance(
+        &mut self,
+        owner: Address,
+        spender: Address,
+        amount: U256,
+    ) -> Result<(), Vec<u8>> {
+        let allowance = self.allowance(owner, spender);
+        if allowance < amount {
+            return Err(
+                VendingMachineError::InsufficientAllowance(InsufficientAllowance {
+                    spender,
+                    allowance,
+                    needed: amount,
+                })
+                .into(),
+            );
+        }
+        if allowance == U256::MAX {
+            return Ok(());
+        }
+        self.allowances
+            .setter(owner)
+            .setter(spender)
+            .set(allowance - amount);
+        Ok(())
+    }
+
+    /// Every public transfer goes through here: `to` receives `amount` minus the transfer
+    /// fee, and the fee is burned from `from`.
+    fn transfer_with_fee(
+        &mut self,
+        from: Address,
+        to: Address,
+        amount: U256,
+    ) -> Result<(), Vec<u8>> {
+        let fee = self.transfer_fee_for(amount);
+        if fee.is_zero() {
+            return self.transfer_internal(from, to, amount);
+        }
+
+        self.ensure_balance(from, amount)?;
+        self.transfer_internal(from, to, amount - fee)?;
+        self.burn(from, DEFAULT_FLAVOR, fee, BALANCE_REASON_BURN)?;
+        log(
+            self.vm(),
+            Transfer {
+                from,
+                to: Address::ZERO,
+                value: fee,
+            },
+        );
+        Ok(())
+    }
+
+    fn transfer_internal(
+        &mut self,
+        from: Address,
+        to: Address,
+        amount: U256,
+    ) -> Result<(), Vec<u8>> {
+        if from == to && self.reject_self_transfers.get() {
+            return Err(VendingMachineError::SelfTransfer(SelfTransfer { user: from }).into());
+        }
+        self.ensure_not_blacklisted(from)?;
+        self.ensure_not_blacklisted(to)?;
+        self.ensure_not_frozen(from)?;
+        self.ensure_not_frozen(to)?;
+
+        self.materialize_decay(from, DEFAULT_FLAVOR);
+        self.materialize_decay(to, DEFAULT_FLAVOR);
+        let balance = self.flavor_balance(from, DEFAULT_FLAVOR);
+        if balance < amount {
+            return Err(
+                VendingMachineError::InsufficientBalance(InsufficientBalance {
+                    from,
+                    balance,
+                    needed: amount,
+                })
+                .into(),
+            );
+        }
+        self.set_flavor_balance(
+            from,
+            DEFAULT_FLAVOR,
+            balance - amount,
+            BALANCE_REASON_TRANSFER_OUT,
+        );
+        let to_balance = self.flavor_balance(to, DEFAULT_FLAVOR) + amount;
+        self.set_flavor_balance(to, DEFAULT_FLAVOR, to_balance, BALANCE_REASON_TRANSFER_IN);
+
+        if self.anti_circumvention.get() {
+            let mut flavor_times = self.cupcake_distribution_times.setter(DEFAULT_FLAVOR);
+            let from_time = flavor_times.get(from);
+            let mut to_time = flavor_times.setter(to);
+            if from_time > to_time.get() {
+                to_time.set(from_time);
+            }
+        }
+
+        log(
+            self.vm(),
+            Transfer {
+                from,
+                to,
+                value: amount,
+            },
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Calls seen by the test override of `on_cupcake_given`.
+    static GIVEN_HOOK_CALLS: core::cell::RefCell<Vec<(Address, U256)>> = const {
+        core::cell::RefCell::new(Vec::new())
+    };
+}
+
+/// Test override that records every call, standing in for what a fork would do.
+#[cfg(test)]
+impl GrantHooks for VendingMachine {
+    fn on_cupcake_given(&mut self, user: Address, amount: U256) {
+        GIVEN_HOOK_CALLS.with(|calls| calls.borrow_mut().push((user, amount)));
+    }
+}
+
+#[cfg(all(test, not(feature = "no-admin")))]
+mod test {
+    use super::*;
+    use alloy_primitives::{address, FixedBytes, B256};
+    use alloy_sol_types::SolEvent;
+    use stylus_sdk::testing::*;
+
+    #[test]
+    fn test_give_cupcake_to() {
+        let vm = TestVM::default();
+
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+
+        vm.set_block_timestamp(vm.block_timestamp() + 6);
+
+        // Give a cupcake and verify it succeeds
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // Check balance is now 1
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+
+        // Try to give another cupcake immediately - should fail due to time restriction
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // Balance should still be 1
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+
+        // Advance block timestamp by 6 seconds
+        vm.set_block_timestamp(vm.block_timestamp() + 6);
+
+        // Now giving a cupcake should succeed
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // Balance should now be 2
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+    }
+    /// This test demonstrates advanced configuration and usage of the TestVM for
+    /// comprehensive smart contract testing.
+    ///
+    /// It covers:
+    /// - Creating and configuring a TestVM with custom parameters
+    /// - Setting blockchain state (timestamps, block numbers)
+    /// - Interacting with contract methods
+    /// - Taking and inspecting VM state snapshots
+    /// - Mocking external contract calls
+    /// - Testing time-dependent contract behavior
+    #[test]
+    fn test_advanced_testvm_configuration() {
+        // SECTION 1: TestVM Setup and Configuration
+        // -----------------------------------------
+
+        // Create a TestVM with custom configuration using the builder pattern
+        // This approach allows for fluent, readable test setup
+        let vm: TestVM = TestVMBuilder::new()
+            // Set the transaction sender address (msg.sender in Solidity)
+            .sender(address!("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"))
+            // Set the address where our contract is deployed
+            .contract_address(address!("0x5FbDB2315678afecb367f032d93F642f64180aa3"))
+            // Set the ETH value sent with the transaction (msg.value in Solidity)
+            .value(U256::from(1))
+            .build();
+
+        // Configure additional blockchain state parameters directly on the VM instance
+        // This demonstrates how to set parameters after VM creation
+        vm.set_block_number(12345678);
+
+        // Note: The chain ID is set to 42161 (Arbitrum One) by default in the TestVM
+        // We don't need to set it explicitly as it's already configured in the VM state
+
+        // SECTION 2: Contract Initialization and User Setup
+        // ------------------------------------------------
+
+        // Initialize our VendingMachine contract with the configured VM
+        // The `from` method connects our contract to the test environment
+        let mut contract = VendingMachine::from(&vm);
+
+        // Define a user address that will interact with our contract
+        // This represents an external user's Ethereum address
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        // SECTION 3: Initial State Verification
+        // ------------------------------------
+
+        // Verify the user starts with zero cupcakes
+        // This confirms our contract's initial state is as expected
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+
+        // Set the initial block timestamp by advancing it by 10 seconds
+        // This ensures we're past any time-based restrictions
+        vm.set_block_timestamp(vm.block_timestamp() + 10);
+
+        // SECTION 4: Contract Interaction
+        // ------------------------------
+
+        // Give a cupcake to the user and verify the operation succeeds
+        // The contract should return true when a cupcake is successfully given
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // Verify the user now has exactly one cupcake
+        // This confirms our contract correctly updated its storage
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+
+        // SECTION 5: VM State Inspection
+        // -----------------------------
+
+        // Take a snapshot of the current VM state for inspection
+        // This captures all storage, balances, and blockchain parameters
+        let snapshot = vm.snapshot();
+
+        // Inspect various aspects of the VM state to verify configuration
+        // Chain ID should be Arbitrum One (42161) which is the default
+        assert_eq!(snapshot.chain_id, 42161);
+        // Message value should match what we configured (1 wei)
+        assert_eq!(snapshot.msg_value, U256::from(1));
+
+        // SECTION 6: Mocking External Contract Calls
+        // -----------------------------------------
+
+        // Define an external contract we might want to interact with
+        let external_contract = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        // Define example call data we would send to that contract
+        let call_data = vec![0xab, 0xcd, 0xef];
+        // Define the expected response from that contract
+        let expected_response = vec![0x12, 0x34, 0x56];
+
+        // Mock the external call so it returns our expected response
+        // This allows testing contract interactions without deploying external contracts
+        vm.mock_call(external_contract, call_data, Ok(expected_response));
+
+        // SECTION 7: Time-Dependent Behavior Testing
+        // -----------------------------------------
+
+        // Set a specific block timestamp
+        // This simulates the passage of time on the blockchain
+        vm.set_block_timestamp(1006);
+
+        // Try giving another cupcake after the time restriction has passed
+        // The contract should allow this since enough time has elapsed
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // Verify the user now has two cupcakes
+        // This confirms our contract correctly handles time-based restrictions
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+    }
+
+    #[test]
+    fn test_block_contracts() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+
+        let eoa = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bot = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        vm.set_code(bot, vec![0x60, 0x00]);
+        vm.set_block_timestamp(vm.block_timestamp() + 6);
+
+        // With the guard off, contracts can still receive cupcakes
+        assert!(contract.give_cupcake_to(bot, DEFAULT_FLAVOR).unwrap());
+
+        contract.set_block_contracts(true).unwrap();
+        vm.set_block_timestamp(vm.block_timestamp() + 6);
+
+        // Contract recipients are rejected, EOAs still pass
+        let err: Vec<u8> =
+            VendingMachineError::ContractRecipient(ContractRecipient { recipient: bot }).into();
+        assert_eq!(contract.give_cupcake_to(bot, DEFAULT_FLAVOR), Err(err));
+        assert!(contract.give_cupcake_to(eoa, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(bot, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+
+        // Only the owner can toggle the guard
+        vm.set_sender(eoa);
+        assert!(contract.set_block_contracts(false).is_err());
+    }
+
+    #[test]
+    fn test_mint_transfer_events() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let keeper = address!("0x1111111111111111111111111111111111111111");
+        contract.set_keeper_reward(U256::from(1)).unwrap();
+        let mints_since = |seen: usize| -> Vec<(Address, U256)> {
+            vm.get_emitted_logs()[seen..]
+                .iter()
+                .filter(|(topics, _)| topics[0] == Transfer::SIGNATURE_HASH)
+                .map(|(topics, data)| {
+                    Transfer::decode_raw_log(topics.iter().copied(), data, true).unwrap()
+                })
+                .filter(|event| event.from.is_zero())
+                .map(|event| (event.to, event.value))
+                .collect()
+        };
+
+        // A free claim is a mint from the zero address, like any other
+        vm.set_block_timestamp(100);
+        let seen = vm.get_emitted_logs().len();
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(mints_since(seen), vec![(user, U256::from(1))]);
+
+        vm.set_sender(keeper);
+        vm.set_block_timestamp(200);
+        let seen = vm.get_emitted_logs().len();
+        assert_eq!(
+            contract.give_cupcakes_to(vec![user], false),
+            Ok(U256::from(1))
+        );
+        assert_eq!(
+            mints_since(seen),
+            vec![(user, U256::from(1)), (keeper, U256::from(1))]
+        );
+
+        // Other flavors aren't part of the token
+        let seen = vm.get_emitted_logs().len();
+        assert!(contract.give_cupcake_to(user, U256::from(7)).unwrap());
+        assert!(mints_since(seen).is_empty());
+    }
+
+    #[test]
+    fn test_user_claim_event() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(10);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(20);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        let logs: Vec<_> = vm
+            .get_emitted_logs()
+            .into_iter()
+            .filter(|(topics, _)| topics[0] == UserClaim::SIGNATURE_HASH)
+            .collect();
+        assert_eq!(logs.len(), 2);
+        for (i, (topics, data)) in logs.iter().enumerate() {
+            let event = UserClaim::decode_raw_log(topics.iter().copied(), data, true).unwrap();
+            assert_eq!(event.user, user);
+            assert_eq!(event.claimNumber, U256::from(i + 1));
+            assert_eq!(event.timestamp, U256::from(10 * (i + 1)));
+            // The user and claim number are indexed so histories can be filtered by topic
+            assert_eq!(topics[1], B256::left_padding_from(user.as_slice()));
+            assert_eq!(topics[2], B256::from(U256::from(i + 1)));
+        }
+    }
+
+    #[test]
+    fn test_batch_transfer() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let holder = vm.msg_sender();
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+
+        for _ in 0..5 {
+            vm.set_block_timestamp(vm.block_timestamp() + 6);
+            assert!(contract.give_cupcake_to(holder, DEFAULT_FLAVOR).unwrap());
+        }
+
+        contract
+            .batch_transfer(vec![alice, bob], vec![U256::from(2), U256::from(1)])
+            .unwrap();
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(holder, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(alice, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(bob, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+
+        // Mismatched inputs are rejected
+        let err: Vec<u8> = VendingMachineError::LengthMismatch(LengthMismatch {}).into();
+        assert_eq!(
+            contract.batch_transfer(vec![alice, bob], vec![U256::from(1)]),
+            Err(err)
+        );
+    }
+
+    #[test]
+    fn test_batch_transfer_over_budget_reverts() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let holder = vm.msg_sender();
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+
+        for _ in 0..3 {
+            vm.set_block_timestamp(vm.block_timestamp() + 6);
+            assert!(contract.give_cupcake_to(holder, DEFAULT_FLAVOR).unwrap());
+        }
+
+        // The second transfer exceeds the running balance, so nothing moves
+        let err: Vec<u8> = VendingMachineError::InsufficientBalance(InsufficientBalance {
+            from: holder,
+            balance: U256::from(3),
+            needed: U256::from(4),
+        })
+        .into();
+        assert_eq!(
+            contract.batch_transfer(vec![alice, bob], vec![U256::from(2), U256::from(2)]),
+            Err(err)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(holder, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(3)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(alice, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(bob, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_reward_token_payout() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let token = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let amount = U256::from(100);
+
+        let calldata = IERC20::transferCall { to: user, amount }.abi_encode();
+        let success = IERC20::transferCall::abi_encode_returns(&(true,));
+
+        // With no reward token configured, no external call is made
+        vm.set_block_timestamp(6);
+        vm.mock_call(token, calldata.clone(), Err(vec![]));
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // The mocked revert only matches `transfer(user, 100)`, proving the exact call is made
+        contract.set_reward_token(token, amount).unwrap();
+        vm.set_block_timestamp(12);
+        let err: Vec<u8> =
+            VendingMachineError::TokenTransferFailed(TokenTransferFailed { token }).into();
+        assert_eq!(contract.give_cupcake_to(user, DEFAULT_FLAVOR), Err(err));
+
+        vm.mock_call(token, calldata, Ok(success));
+        vm.set_block_timestamp(18);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+    }
+
+    #[test]
+    fn test_my_balance() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(6);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        vm.set_sender(user);
+        assert_eq!(
+            contract.my_balance().unwrap(),
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap()
+        );
+        assert_eq!(contract.my_balance().unwrap(), U256::from(1));
+    }
+
+    #[test]
+    fn test_cooldown_views() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.can_receive_cupcake(user).unwrap());
+        assert_eq!(contract.time_until_next_cupcake(user).unwrap(), U256::ZERO);
+
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(102);
+        assert!(!contract.can_receive_cupcake(user).unwrap());
+        assert_eq!(
+            contract.time_until_next_cupcake(user).unwrap(),
+            U256::from(3)
+        );
+    }
+
+    #[test]
+    fn test_future_distribution_time_saturates() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        // Simulate imported state with a distribution time ahead of the chain
+        vm.set_block_timestamp(100);
+        contract
+            .cupcake_distribution_times
+            .setter(DEFAULT_FLAVOR)
+            .setter(user)
+            .set(U256::from(1_000));
+
+        assert_eq!(contract.elapsed_since(user, DEFAULT_FLAVOR), U256::ZERO);
+        assert!(!contract.can_receive_cupcake(user).unwrap());
+        assert_eq!(
+            contract.time_until_next_cupcake(user).unwrap(),
+            U256::from(COOLDOWN_SECONDS)
+        );
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+    }
+
+    #[test]
+    fn test_daily_limit() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_daily_limit(U256::from(2)).unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        // Two claims fit in the first day
+        vm.set_block_timestamp(10);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(20);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // The third one hits the wall even though the cooldown has passed
+        vm.set_block_timestamp(30);
+        let err: Vec<u8> = VendingMachineError::DailyLimitReached(DailyLimitReached {
+            user,
+            limit: U256::from(2),
+        })
+        .into();
+        assert_eq!(contract.give_cupcake_to(user, DEFAULT_FLAVOR), Err(err));
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+
+        // The count resets once the day rolls over
+        vm.set_block_timestamp(SECONDS_PER_DAY + 10);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(3)
+        );
+    }
+
+    #[test]
+    fn test_effective_grant_amount() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        // Default grant is one cupcake
+        vm.set_block_timestamp(10);
+        let expected = contract.effective_grant_amount(user).unwrap();
+        assert_eq!(expected, U256::from(1));
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            expected
+        );
+
+        // A configured base grant is reflected in both the preview and the payout
+        contract.set_grant_amount(U256::from(3)).unwrap();
+        vm.set_block_timestamp(20);
+        let before = contract
+            .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+            .unwrap();
+        let expected = contract.effective_grant_amount(user).unwrap();
+        assert_eq!(expected, U256::from(3));
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap()
+                - before,
+            expected
+        );
+
+        // Auto-compounding adds the streak the claim is about to reach
+        vm.set_sender(user);
+        contract.enable_auto_compound(true).unwrap();
+        vm.set_block_timestamp(30);
+        let before = contract
+            .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+            .unwrap();
+        let expected = contract.effective_grant_amount(user).unwrap();
+        assert_eq!(expected, U256::from(3 + 3));
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap()
+                - before,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_cooldown_waiver() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(100);
+        contract.set_cooldown_waived_until(U256::from(110)).unwrap();
+        assert!(contract.is_cooldown_waived());
+
+        // Rapid claims succeed while the waiver is active
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(101);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(3)
+        );
+
+        // Once it expires the cooldown applies again
+        vm.set_block_timestamp(110);
+        assert!(!contract.is_cooldown_waived());
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(111);
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+    }
+
+    #[test]
+    fn test_approval_events() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let owner = vm.msg_sender();
+        let spender = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        let last_approval = |vm: &TestVM| {
+            let (topics, data) = vm.get_emitted_logs().pop().unwrap();
+            Approval::decode_raw_log(topics.iter().copied(), &data, true).unwrap()
+        };
+
+        contract.approve(spender, U256::from(10)).unwrap();
+        let event = last_approval(&vm);
+        assert_eq!((event.owner, event.spender), (owner, spender));
+        assert_eq!(event.value, U256::from(10));
+
+        // Increases and decreases report the resulting allowance
+        contract.increase_allowance(spender, U256::from(5)).unwrap();
+        assert_eq!(last_approval(&vm).value, U256::from(15));
+        contract
+            .decrease_allowance(spender, U256::from(12))
+            .unwrap();
+        assert_eq!(last_approval(&vm).value, U256::from(3));
+        assert_eq!(contract.allowance(owner, spender), U256::from(3));
+
+        // Decreasing below zero is rejected
+        assert!(contract.decrease_allowance(spender, U256::from(4)).is_err());
+    }
+
+    #[test]
+    fn test_transfer_from() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let holder = vm.msg_sender();
+        let spender = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let recipient = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+
+        for _ in 0..3 {
+            vm.set_block_timestamp(vm.block_timestamp() + 6);
+            assert!(contract.give_cupcake_to(holder, DEFAULT_FLAVOR).unwrap());
+        }
+        contract.approve(spender, U256::from(2)).unwrap();
+
+        vm.set_sender(spender);
+        assert!(contract
+            .transfer_from(holder, recipient, U256::from(2))
+            .unwrap());
+        assert_eq!(contract.allowance(holder, spender), U256::ZERO);
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(recipient, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+
+        // The allowance is used up
+        assert!(contract
+            .transfer_from(holder, recipient, U256::from(1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_sum_balances() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let a = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let b = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let c = address!("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+
+        for (user, claims) in [(a, 1), (b, 2), (c, 3)] {
+            for _ in 0..claims {
+                vm.set_block_timestamp(vm.block_timestamp() + 6);
+                assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+            }
+        }
+
+        let individual = contract.get_cupcake_balance_for(a, DEFAULT_FLAVOR).unwrap()
+            + contract.get_cupcake_balance_for(b, DEFAULT_FLAVOR).unwrap()
+            + contract.get_cupcake_balance_for(c, DEFAULT_FLAVOR).unwrap();
+        assert_eq!(contract.sum_balances(vec![a, b, c]).unwrap(), individual);
+        assert_eq!(individual, U256::from(6));
+
+        // Duplicates are counted each time
+        assert_eq!(contract.sum_balances(vec![c, c]).unwrap(), U256::from(6));
+    }
+
+    #[test]
+    fn test_min_account_age() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_min_account_age(U256::from(60)).unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        // The first call only records the sighting
+        vm.set_block_timestamp(100);
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+
+        // Retrying before the age is reached reverts
+        vm.set_block_timestamp(130);
+        let err: Vec<u8> = VendingMachineError::AccountTooNew(AccountTooNew {
+            user,
+            eligibleAt: U256::from(160),
+        })
+        .into();
+        assert_eq!(contract.give_cupcake_to(user, DEFAULT_FLAVOR), Err(err));
+
+        // Afterwards the claim goes through
+        vm.set_block_timestamp(160);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+    }
+
+    #[test]
+    fn test_confiscate() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let other = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+
+        contract.set_grant_amount(U256::from(4)).unwrap();
+        vm.set_block_timestamp(10);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert!(contract.give_cupcake_to(other, DEFAULT_FLAVOR).unwrap());
+        let sprinkles = U256::from(7);
+        assert!(contract.give_cupcake_to(user, sprinkles).unwrap());
+        assert_eq!(contract.total_supply(), U256::from(12));
+
+        // Only blacklisted accounts can be confiscated from
+        let err: Vec<u8> = VendingMachineError::NotBlacklisted(NotBlacklisted { user }).into();
+        assert_eq!(contract.confiscate(user), Err(err));
+
+        // Every flavor goes
+        contract.set_blacklisted(user, true).unwrap();
+        assert_eq!(contract.confiscate(user).unwrap(), U256::from(8));
+        for flavor in [DEFAULT_FLAVOR, sprinkles] {
+            assert_eq!(
+                contract.get_cupcake_balance_for(user, flavor).unwrap(),
+                U256::ZERO
+            );
+        }
+        assert_eq!(contract.flavor_total_supply(sprinkles), U256::ZERO);
+        assert_eq!(contract.total_supply(), U256::from(4));
+
+        // Blacklisted accounts can no longer claim
+        vm.set_block_timestamp(20);
+        let err: Vec<u8> = VendingMachineError::Blacklisted(Blacklisted { user }).into();
+        assert_eq!(contract.give_cupcake_to(user, DEFAULT_FLAVOR), Err(err));
+    }
+
+    #[test]
+    fn test_grace_period() {
+        let vm = TestVM::default();
+        vm.set_block_timestamp(1_000);
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_grace_period(U256::from(60)).unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        // Two quick claims inside the grace window both succeed
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(1_001);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+
+        // After the window the cooldown is back
+        vm.set_block_timestamp(1_060);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(1_061);
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+    }
+
+    #[test]
+    fn test_user_status() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let eligible = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let cooling = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+
+        vm.set_block_timestamp(10);
+        assert!(contract.give_cupcake_to(eligible, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(20);
+        assert!(contract.give_cupcake_to(cooling, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(22);
+
+        assert_eq!(
+            contract.user_status(eligible).unwrap(),
+            (U256::from(1), true, U256::ZERO)
+        );
+        assert_eq!(
+            contract.user_status(cooling).unwrap(),
+            (U256::from(1), false, U256::from(3))
+        );
+    }
+
+    /// Builds the `ecrecover` input for a permit and mocks the precompile to return `signer`.
+    /// The TestVM has no precompiles, but the mock only matches the exact digest the
+    /// contract is expected to compute.
+    fn mock_permit_signature(
+        vm: &TestVM,
+        contract: &VendingMachine,
+        permit: &Permit,
+        signer: Address,
+    ) -> (u8, B256, B256) {
+        let (v, r, s) = (27u8, B256::repeat_byte(0x11), B256::repeat_byte(0x22));
+        let digest = permit.eip712_signing_hash(&contract.eip712_domain());
+        let mut input = digest.to_vec();
+        input.extend_from_slice(&B256::from(U256::from(v))[..]);
+        input.extend_from_slice(r.as_slice());
+        input.extend_from_slice(s.as_slice());
+        vm.mock_static_call(
+            ECRECOVER,
+            input,
+            Ok(B256::left_padding_from(signer.as_slice()).to_vec()),
+        );
+        (v, r, s)
+    }
+
+    #[test]
+    fn test_permit() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let owner = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let spender = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let deadline = U256::from(1_000);
+        vm.set_block_timestamp(500);
+
+        let permit = Permit {
+            owner,
+            spender,
+            value: U256::from(7),
+            nonce: U256::ZERO,
+            deadline,
+        };
+        let (v, r, s) = mock_permit_signature(&vm, &contract, &permit, owner);
+
+        // Anyone can submit the signed permit
+        contract
+            .permit(owner, spender, U256::from(7), deadline, v, r, s)
+            .unwrap();
+        assert_eq!(contract.allowance(owner, spender), U256::from(7));
+        assert_eq!(contract.nonces(owner), U256::from(1));
+
+        // Replaying it fails because the nonce moved on
+        let err: Vec<u8> = VendingMachineError::InvalidSignature(InvalidSignature {}).into();
+        assert_eq!(
+            contract.permit(owner, spender, U256::from(7), deadline, v, r, s),
+            Err(err)
+        );
+    }
+
+    #[test]
+    fn test_permit_expired() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let owner = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let spender = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let deadline = U256::from(1_000);
+
+        let permit = Permit {
+            owner,
+            spender,
+            value: U256::from(7),
+            nonce: U256::ZERO,
+            deadline,
+        };
+        let (v, r, s) = mock_permit_signature(&vm, &contract, &permit, owner);
+
+        vm.set_block_timestamp(1_001);
+        let err: Vec<u8> =
+            VendingMachineError::SignatureExpired(SignatureExpired { deadline }).into();
+        assert_eq!(
+            contract.permit(owner, spender, U256::from(7), deadline, v, r, s),
+            Err(err)
+        );
+        assert_eq!(contract.allowance(owner, spender), U256::ZERO);
+        assert_eq!(contract.nonces(owner), U256::ZERO);
+    }
+
+    #[test]
+    fn test_redeem_voucher() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = vm.msg_sender();
+        let deadline = U256::from(100);
+
+        vm.set_block_timestamp(90);
+        assert!(contract.redeem_voucher(deadline).unwrap());
+        // The cooldown still applies within the window
+        assert!(!contract.redeem_voucher(deadline).unwrap());
+
+        vm.set_block_timestamp(101);
+        let err: Vec<u8> = VendingMachineError::VoucherExpired(VoucherExpired { deadline }).into();
+        assert_eq!(contract.redeem_voucher(deadline), Err(err));
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+    }
+
+    #[test]
+    fn test_adjust_balance() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        let credited = contract
+            .adjust_balance(user, I256::try_from(5).unwrap())
+            .unwrap();
+        assert_eq!(credited, U256::from(5));
+        assert_eq!(contract.total_supply(), U256::from(5));
+
+        let debited = contract
+            .adjust_balance(user, I256::try_from(-3).unwrap())
+            .unwrap();
+        assert_eq!(debited, U256::from(2));
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+        assert_eq!(contract.total_supply(), U256::from(2));
+
+        // Debiting more than the balance is rejected
+        let err: Vec<u8> = VendingMachineError::InsufficientBalance(InsufficientBalance {
+            from: user,
+            balance: U256::from(2),
+            needed: U256::from(3),
+        })
+        .into();
+        assert_eq!(
+            contract.adjust_balance(user, I256::try_from(-3).unwrap()),
+            Err(err)
+        );
+        assert_eq!(contract.total_supply(), U256::from(2));
+    }
+
+    #[test]
+    fn test_cooldown_grows_with_balance() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract
+            .set_cooldown_growth(U256::from(10), U256::from(25))
+            .unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(
+            contract.get_effective_cooldown(user).unwrap(),
+            U256::from(5)
+        );
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract.get_effective_cooldown(user).unwrap(),
+            U256::from(15)
+        );
+
+        // The longer wait is enforced
+        vm.set_block_timestamp(110);
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(115);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // Two cupcakes would mean 25 seconds, which is exactly the cap
+        assert_eq!(
+            contract.get_effective_cooldown(user).unwrap(),
+            U256::from(25)
+        );
+        vm.set_block_timestamp(140);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract.get_effective_cooldown(user).unwrap(),
+            U256::from(25)
+        );
+    }
+
+    #[test]
+    fn test_paused_blocks_mutations() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let one = U256::from(1);
+
+        contract.pause().unwrap();
+        assert!(contract.paused());
+
+        let err: Vec<u8> = VendingMachineError::ContractPaused(ContractPaused {}).into();
+        let results = [
+            contract.give_cupcake_to(user, DEFAULT_FLAVOR).map(|_| ()),
+            contract.redeem_voucher(U256::MAX).map(|_| ()),
+            contract.transfer_cupcake(user, one).map(|_| ()),
+            contract.batch_transfer(vec![user], vec![one]),
+            contract.approve(user, one).map(|_| ()),
+            contract.increase_allowance(user, one).map(|_| ()),
+            contract.decrease_allowance(user, one).map(|_| ()),
+            contract.transfer_from(user, user, one).map(|_| ()),
+            contract.permit(user, user, one, U256::MAX, 27, B256::ZERO, B256::ZERO),
+            contract.clear_distribution_time(user),
+        ];
+        for result in results {
+            assert_eq!(result, Err(err.clone()));
+        }
+
+        contract.unpause().unwrap();
+        vm.set_block_timestamp(10);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+    }
+
+    #[test]
+    fn test_metadata_uri() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        assert_eq!(contract.metadata_uri(), "");
+
+        let uri =
+            String::from("ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi");
+        contract.set_metadata_uri(uri.clone()).unwrap();
+        assert_eq!(contract.metadata_uri(), uri);
+
+        let too_long = "a".repeat(MAX_METADATA_URI_LENGTH + 1);
+        let err: Vec<u8> = VendingMachineError::MetadataUriTooLong(MetadataUriTooLong {
+            length: U256::from(MAX_METADATA_URI_LENGTH + 1),
+            max: U256::from(MAX_METADATA_URI_LENGTH),
+        })
+        .into();
+        assert_eq!(contract.set_metadata_uri(too_long), Err(err));
+        assert_eq!(contract.metadata_uri(), uri);
+    }
+
+    #[test]
+    fn test_claim_streak_bonus() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract
+            .set_streak_config(U256::from(60), U256::from(3), U256::from(10))
+            .unwrap();
+        let user = vm.msg_sender();
+
+        // Two claims are not enough yet
+        for t in [100, 150] {
+            vm.set_block_timestamp(t);
+            assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        }
+        let err: Vec<u8> = VendingMachineError::StreakTooShort(StreakTooShort {
+            streak: U256::from(2),
+            threshold: U256::from(3),
+        })
+        .into();
+        assert_eq!(contract.claim_streak_bonus(), Err(err));
+
+        // A gap longer than the window restarts the streak
+        vm.set_block_timestamp(300);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(contract.get_streak(user), U256::from(1));
+
+        for t in [350, 400] {
+            vm.set_block_timestamp(t);
+            assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        }
+        assert_eq!(contract.get_streak(user), U256::from(3));
+
+        let before = contract
+            .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+            .unwrap();
+        assert_eq!(contract.claim_streak_bonus().unwrap(), U256::from(10));
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            before + U256::from(10)
+        );
+        assert_eq!(contract.get_streak(user), U256::ZERO);
+        assert!(contract.claim_streak_bonus().is_err());
+    }
+
+    #[test]
+    fn test_flavors_have_independent_cooldowns() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let mint_flavor = U256::from(1);
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        // A different flavor is not blocked by the default flavor's cooldown
+        assert!(contract.give_cupcake_to(user, mint_flavor).unwrap());
+        assert!(!contract.give_cupcake_to(user, mint_flavor).unwrap());
+
+        assert_eq!(
+            contract.get_cupcake_balance_for(user, mint_flavor).unwrap(),
+            U256::from(1)
+        );
+        // The single-flavor ABI still reads and writes the default flavor
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(1)
+        );
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(2)
+        );
+        assert_eq!(contract.total_supply(), U256::from(3));
+    }
+
+    #[test]
+    fn test_set_user_cooldowns() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let vips = vec![
+            address!("0xCDC41bff86a62716f050622325CC17a317f99404"),
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+        ];
+        let regular = address!("0x3333333333333333333333333333333333333333");
+
+        contract
+            .set_user_cooldowns(vips.clone(), U256::from(1))
+            .unwrap();
+        for vip in &vips {
+            assert_eq!(
+                contract.get_effective_cooldown(*vip).unwrap(),
+                U256::from(1)
+            );
+        }
+        assert_eq!(
+            contract.get_effective_cooldown(regular).unwrap(),
+            U256::from(COOLDOWN_SECONDS)
+        );
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(vips[0], DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(101);
+        assert!(contract.give_cupcake_to(vips[0], DEFAULT_FLAVOR).unwrap());
+
+        vm.set_sender(regular);
+        assert!(contract
+            .set_user_cooldowns(vec![regular], U256::ZERO)
+            .is_err());
+    }
+
+    #[test]
+    fn test_is_sold_out_unbounded() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        assert!(!contract.is_sold_out().unwrap());
+        for i in 1..=3 {
+            vm.set_block_timestamp(i * 100);
+            assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        }
+        assert_eq!(contract.total_distributed(), U256::from(3));
+        assert!(!contract.is_sold_out().unwrap());
+    }
+
+    #[test]
+    fn test_is_sold_out_capped() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_max_supply(U256::from(2)).unwrap();
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x1111111111111111111111111111111111111111");
+        let carol = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(alice, DEFAULT_FLAVOR).unwrap());
+        assert!(!contract.is_sold_out().unwrap());
+        assert!(contract.give_cupcake_to(bob, U256::from(1)).unwrap());
+        assert!(contract.is_sold_out().unwrap());
+
+        let err: Vec<u8> = VendingMachineError::SoldOut(SoldOut {
+            maxSupply: U256::from(2),
+        })
+        .into();
+        assert_eq!(contract.give_cupcake_to(carol, DEFAULT_FLAVOR), Err(err));
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(carol, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_sweep_reward_tokens() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let treasury = address!("0x1111111111111111111111111111111111111111");
+        let token = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let amount = U256::from(500);
+        let failed: Vec<u8> =
+            VendingMachineError::TokenTransferFailed(TokenTransferFailed { token }).into();
+
+        // Nothing to sweep until a reward token is configured
+        assert!(contract.sweep_reward_tokens(treasury, amount).is_err());
+
+        contract.set_reward_token(token, U256::from(100)).unwrap();
+        let calldata = IERC20::transferCall {
+            to: treasury,
+            amount,
+        }
+        .abi_encode();
+        vm.mock_call(
+            token,
+            calldata.clone(),
+            Ok(IERC20::transferCall::abi_encode_returns(&(true,))),
+        );
+        assert!(contract.sweep_reward_tokens(treasury, amount).is_ok());
+
+        // A token that returns false fails the sweep
+        vm.mock_call(
+            token,
+            calldata.clone(),
+            Ok(IERC20::transferCall::abi_encode_returns(&(false,))),
+        );
+        assert_eq!(
+            contract.sweep_reward_tokens(treasury, amount),
+            Err(failed.clone())
+        );
+
+        // As does a reverting one
+        vm.mock_call(token, calldata, Err(vec![]));
+        assert_eq!(contract.sweep_reward_tokens(treasury, amount), Err(failed));
+
+        vm.set_sender(treasury);
+        assert!(contract.sweep_reward_tokens(treasury, amount).is_err());
+    }
+
+    #[test]
+    fn test_give_cupcakes_to_lenient() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x1111111111111111111111111111111111111111");
+        let banned = address!("0x2222222222222222222222222222222222222222");
+        contract.set_blacklisted(banned, true).unwrap();
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(alice, DEFAULT_FLAVOR).unwrap());
+
+        // Alice is cooling down and `banned` is blacklisted, so only Bob is served
+        assert_eq!(
+            contract.give_cupcakes_to(vec![alice, bob, banned], false),
+            Ok(U256::from(1))
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(alice, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(bob, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(banned, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_give_cupcakes_to_strict() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x1111111111111111111111111111111111111111");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(alice, DEFAULT_FLAVOR).unwrap());
+
+        let err: Vec<u8> =
+            VendingMachineError::IneligibleRecipient(IneligibleRecipient { user: alice }).into();
+        assert_eq!(contract.give_cupcakes_to(vec![bob, alice], true), Err(err));
+        // Nothing was distributed, not even to the eligible user listed first
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(bob, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        assert_eq!(
+            contract.give_cupcakes_to(vec![bob, alice], true),
+            Ok(U256::from(2))
+        );
+
+        // A repeated address is cooling down by its second entry
+        let carol = address!("0x2222222222222222222222222222222222222222");
+        let err: Vec<u8> =
+            VendingMachineError::IneligibleRecipient(IneligibleRecipient { user: carol }).into();
+        assert_eq!(
+            contract.give_cupcakes_to(vec![carol, carol], true),
+            Err(err)
+        );
+    }
+
+    #[test]
+    fn test_give_cupcakes_to_skips_capped_users() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let capped = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x1111111111111111111111111111111111111111");
+        contract.set_daily_limit(U256::from(1)).unwrap();
+        contract
+            .set_cooldown_waived_until(U256::from(1_000))
+            .unwrap();
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(capped).unwrap());
+
+        // A user at the daily limit is skipped instead of reverting the batch
+        assert_eq!(
+            contract.give_cupcakes_to(vec![capped, bob], false),
+            Ok(U256::from(1))
+        );
+        assert_eq!(contract.effective_balance(capped), U256::from(1));
+        assert_eq!(contract.effective_balance(bob), U256::from(1));
+
+        let err: Vec<u8> =
+            VendingMachineError::IneligibleRecipient(IneligibleRecipient { user: capped }).into();
+        assert_eq!(contract.give_cupcakes_to(vec![capped], true), Err(err));
+    }
+
+    #[test]
+    fn test_batch_next_eligible() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x1111111111111111111111111111111111111111");
+        let carol = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(alice, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(102);
+        assert!(contract.give_cupcake_to(bob, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(103);
+
+        let users = vec![alice, bob, carol];
+        let batch = contract.batch_next_eligible(users.clone()).unwrap();
+        let individual: Vec<U256> = users
+            .iter()
+            .map(|user| contract.next_eligible_time(*user).unwrap())
+            .collect();
+        assert_eq!(batch, individual);
+        assert_eq!(
+            batch,
+            vec![
+                U256::from(100 + COOLDOWN_SECONDS),
+                U256::from(102 + COOLDOWN_SECONDS),
+                U256::from(103),
+            ]
+        );
+    }
+
+    /// Claims for `user` until the dispenser turns them away, returning how many
+    /// cupcakes were handed out.
+    fn drain<D: CupcakeDispenser>(dispenser: &mut D, user: Address) -> U256 {
+        let mut given = U256::ZERO;
+        while dispenser.can_receive(user) {
+            assert!(dispenser.give(user).unwrap());
+            given += U256::from(1);
+        }
+        given
+    }
+
+    #[test]
+    fn test_cupcake_dispenser_trait() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(100);
+        assert_eq!(drain(&mut contract, user), U256::from(1));
+
+        let dispenser: &mut dyn CupcakeDispenser = &mut contract;
+        assert!(!dispenser.can_receive(user));
+        assert!(!dispenser.give(user).unwrap());
+        assert_eq!(dispenser.balance_of(user), U256::from(1));
+        assert_eq!(
+            dispenser.balance_of(user),
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_try_give_reason_codes() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bot = address!("0x1111111111111111111111111111111111111111");
+        let banned = address!("0x2222222222222222222222222222222222222222");
+        let newcomer = address!("0x3333333333333333333333333333333333333333");
+
+        vm.set_block_timestamp(100);
+        assert_eq!(contract.try_give(user), Ok(REASON_OK));
+        assert_eq!(contract.try_give(user), Ok(REASON_COOLDOWN));
+
+        contract.pause().unwrap();
+        assert_eq!(contract.try_give(bot), Ok(REASON_PAUSED));
+        contract.unpause().unwrap();
+
+        contract.set_blacklisted(banned, true).unwrap();
+        assert_eq!(contract.try_give(banned), Ok(REASON_BLACKLISTED));
+
+        contract.set_block_contracts(true).unwrap();
+        vm.set_code(bot, vec![0x60, 0x00]);
+        assert_eq!(contract.try_give(bot), Ok(REASON_CONTRACT));
+        contract.set_block_contracts(false).unwrap();
+
+        contract.set_min_account_age(U256::from(60)).unwrap();
+        assert_eq!(contract.try_give(newcomer), Ok(REASON_TOO_NEW));
+        contract.set_daily_limit(U256::from(1)).unwrap();
+        // The first sighting was recorded, so the newcomer qualifies a minute later
+        vm.set_block_timestamp(160);
+        assert_eq!(contract.try_give(newcomer), Ok(REASON_OK));
+        contract.set_min_account_age(U256::ZERO).unwrap();
+
+        vm.set_block_timestamp(200);
+        assert_eq!(contract.try_give(newcomer), Ok(REASON_DAILY_LIMIT));
+        contract.set_daily_limit(U256::ZERO).unwrap();
+
+        // Every claim so far landed in the same block
+        contract.set_max_claims_per_block(U256::from(2)).unwrap();
+        assert_eq!(contract.try_give(bot), Ok(REASON_BLOCK_LIMIT));
+        contract.set_max_claims_per_block(U256::ZERO).unwrap();
+
+        contract.set_max_supply(U256::from(2)).unwrap();
+        assert_eq!(contract.try_give(user), Ok(REASON_SOLD_OUT));
+        assert_eq!(contract.total_supply(), U256::from(2));
+    }
+
+    #[test]
+    fn test_keeper_reward() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let keeper = address!("0x1111111111111111111111111111111111111111");
+        let users = vec![
+            address!("0xCDC41bff86a62716f050622325CC17a317f99404"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+        ];
+
+        let err: Vec<u8> = VendingMachineError::KeeperRewardTooHigh(KeeperRewardTooHigh {
+            reward: U256::from(2),
+            max: U256::from(1),
+        })
+        .into();
+        assert_eq!(contract.set_keeper_reward(U256::from(2)), Err(err));
+        contract.set_keeper_reward(U256::from(1)).unwrap();
+
+        vm.set_sender(keeper);
+        vm.set_block_timestamp(100);
+        assert_eq!(
+            contract.give_cupcakes_to(users.clone(), false),
+            Ok(U256::from(3))
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(keeper, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(3)
+        );
+
+        // Everyone is cooling down, so nothing is granted and nothing is earned
+        assert_eq!(
+            contract.give_cupcakes_to(users.clone(), false),
+            Ok(U256::ZERO)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(keeper, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(3)
+        );
+    }
+
+    #[test]
+    fn test_keeper_reward_respects_supply_cap() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_keeper_reward(U256::from(1)).unwrap();
+        contract.set_max_supply(U256::from(3)).unwrap();
+        let keeper = address!("0x1111111111111111111111111111111111111111");
+        let users = vec![
+            address!("0xCDC41bff86a62716f050622325CC17a317f99404"),
+            address!("0x2222222222222222222222222222222222222222"),
+        ];
+
+        vm.set_sender(keeper);
+        vm.set_block_timestamp(100);
+        assert_eq!(contract.give_cupcakes_to(users, false), Ok(U256::from(2)));
+        // Two grants earn two cupcakes, but only one is left under the cap
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(keeper, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+        assert!(contract.is_sold_out().unwrap());
+    }
+
+    #[test]
+    fn test_clear_distribution_time() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let stored = |contract: &VendingMachine| {
+            contract
+                .cupcake_distribution_times
+                .getter(DEFAULT_FLAVOR)
+                .get(user)
+        };
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        // A waiver doesn't make a cooling-down user clearable
+        contract
+            .set_cooldown_waived_until(U256::from(1_000))
+            .unwrap();
+        vm.set_block_timestamp(102);
+        let err: Vec<u8> = VendingMachineError::CooldownActive(CooldownActive {
+            user,
+            remaining: U256::from(COOLDOWN_SECONDS - 2),
+        })
+        .into();
+        assert_eq!(contract.clear_distribution_time(user), Err(err));
+        assert_eq!(stored(&contract), U256::from(100));
+        contract.set_cooldown_waived_until(U256::ZERO).unwrap();
+
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        contract.clear_distribution_time(user).unwrap();
+        assert_eq!(stored(&contract), U256::ZERO);
+        assert!(contract.can_receive_cupcake(user).unwrap());
+    }
+
+    #[test]
+    fn test_clear_distribution_time_keeps_live_streaks() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract
+            .set_streak_config(U256::from(60), U256::from(3), U256::from(1))
+            .unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        vm.set_block_timestamp(150);
+        let err: Vec<u8> = VendingMachineError::StreakActive(StreakActive {
+            user,
+            streak: U256::from(1),
+        })
+        .into();
+        assert_eq!(contract.clear_distribution_time(user), Err(err));
+
+        // Once the window has passed the streak is lost anyway
+        vm.set_block_timestamp(161);
+        contract.clear_distribution_time(user).unwrap();
+    }
+
+    #[test]
+    fn test_version() {
+        let vm = TestVM::default();
+        let contract = VendingMachine::from(&vm);
+
+        assert_eq!(contract.version(), VERSION);
+        let parts: Vec<&str> = VERSION.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part.parse::<u32>().is_ok()));
+    }
+
+    #[test]
+    fn test_claim_ownership_if_unset() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let rescuer = address!("0x1111111111111111111111111111111111111111");
+        let attacker = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_block_timestamp(100);
+        vm.set_sender(rescuer);
+        contract.claim_ownership_if_unset().unwrap();
+        assert_eq!(contract.owner(), rescuer);
+        assert_eq!(contract.start_time(), U256::from(100));
+
+        let err: Vec<u8> = VendingMachineError::AlreadyInitialized(AlreadyInitialized {}).into();
+        vm.set_sender(attacker);
+        assert_eq!(contract.claim_ownership_if_unset(), Err(err.clone()));
+        assert_eq!(contract.init(), Err(err));
+        assert_eq!(contract.owner(), rescuer);
+    }
+
+    #[test]
+    fn test_frozen_account() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let friend = address!("0x1111111111111111111111111111111111111111");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert!(contract.give_cupcake_to(friend, DEFAULT_FLAVOR).unwrap());
+        contract.set_frozen(user, true).unwrap();
+        assert!(contract.is_frozen(user));
+
+        // Reads keep working
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
+        );
+
+        let err: Vec<u8> = VendingMachineError::AccountFrozen(AccountFrozen { user }).into();
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        assert_eq!(
+            contract.give_cupcake_to(user, DEFAULT_FLAVOR),
+            Err(err.clone())
+        );
+
+        vm.set_sender(user);
+        assert_eq!(
+            contract.transfer_cupcake(friend, U256::from(1)),
+            Err(err.clone())
+        );
+        contract.approve(friend, U256::from(1)).unwrap();
+
+        vm.set_sender(friend);
+        assert_eq!(
+            contract.transfer_cupcake(user, U256::from(1)),
+            Err(err.clone())
+        );
+        assert_eq!(
+            contract.transfer_from(user, friend, U256::from(1)),
+            Err(err)
+        );
+
+        vm.set_sender(owner);
+        contract.set_frozen(user, false).unwrap();
+        vm.set_sender(user);
+        assert!(contract.transfer_cupcake(friend, U256::from(1)).unwrap());
+    }
+
+    /// Builds a four-leaf allowlist and returns its root plus each member's proof.
+    fn merkle_allowlist(members: [Address; 4]) -> (B256, Vec<Vec<B256>>) {
+        let leaves = members.map(VendingMachine::merkle_leaf);
+        let left = VendingMachine::merkle_pair(leaves[0], leaves[1]);
+        let right = VendingMachine::merkle_pair(leaves[2], leaves[3]);
+        let root = VendingMachine::merkle_pair(left, right);
+        let proofs = vec![
+            vec![leaves[1], right],
+            vec![leaves[0], right],
+            vec![leaves[3], left],
+            vec![leaves[2], left],
+        ];
+        (root, proofs)
+    }
+
+    #[test]
+    fn test_claim_with_proof() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let members = [
+            address!("0xCDC41bff86a62716f050622325CC17a317f99404"),
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+        ];
+        let outsider = address!("0x4444444444444444444444444444444444444444");
+        let (root, proofs) = merkle_allowlist(members);
+        contract.set_merkle_root(root).unwrap();
+
+        vm.set_block_timestamp(100);
+        for (member, proof) in members.iter().zip(&proofs) {
+            vm.set_sender(*member);
+            assert!(contract.claim_with_proof(proof.clone()).unwrap());
+        }
+
+        let invalid: Vec<u8> = VendingMachineError::InvalidProof(InvalidProof {}).into();
+        vm.set_sender(outsider);
+        assert_eq!(
+            contract.claim_with_proof(proofs[0].clone()),
+            Err(invalid.clone())
+        );
+        vm.set_sender(members[0]);
+        assert_eq!(contract.claim_with_proof(proofs[1].clone()), Err(invalid));
+
+        // Even after the cooldown, a member can only claim once per root
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        let claimed: Vec<u8> = VendingMachineError::AlreadyClaimed(AlreadyClaimed {
+            user: members[0],
+            root,
+        })
+        .into();
+        assert_eq!(contract.claim_with_proof(proofs[0].clone()), Err(claimed));
+
+        // A new root opens a new round
+        let (next_root, next_proofs) =
+            merkle_allowlist([members[0], outsider, members[2], members[3]]);
+        vm.set_sender(owner);
+        contract.set_merkle_root(next_root).unwrap();
+        vm.set_sender(members[0]);
+        assert!(contract.claim_with_proof(next_proofs[0].clone()).unwrap());
+        vm.set_sender(outsider);
+        assert!(contract.claim_with_proof(next_proofs[1].clone()).unwrap());
+    }
+
+    #[test]
+    fn test_merkle_claim_status() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let members = [
+            address!("0xCDC41bff86a62716f050622325CC17a317f99404"),
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+        ];
+        let (root, proofs) = merkle_allowlist(members);
+        assert_eq!(contract.get_merkle_root(), B256::ZERO);
+        contract.set_merkle_root(root).unwrap();
+        assert_eq!(contract.get_merkle_root(), root);
+
+        vm.set_block_timestamp(100);
+        assert!(!contract.has_claimed(members[0]));
+        vm.set_sender(members[0]);
+        assert!(contract.claim_with_proof(proofs[0].clone()).unwrap());
+        assert!(contract.has_claimed(members[0]));
+        assert!(!contract.has_claimed(members[1]));
+
+        vm.set_sender(owner);
+        let newcomer = address!("0x4444444444444444444444444444444444444444");
+        let (next_root, _) = merkle_allowlist([members[0], members[1], members[2], newcomer]);
+        contract.set_merkle_root(next_root).unwrap();
+        assert_eq!(contract.get_merkle_root(), next_root);
+        assert!(!contract.has_claimed(members[0]));
+
+        // Switching back to the old root brings its claims back with it
+        contract.set_merkle_root(root).unwrap();
+        assert!(contract.has_claimed(members[0]));
+    }
+
+    #[test]
+    fn test_transfer_fee_min_fee_on_dust() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let holder = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let friend = address!("0x1111111111111111111111111111111111111111");
+        contract
+            .adjust_balance(holder, I256::try_from(10).unwrap())
+            .unwrap();
+
+        // 1% of a single cupcake rounds down to nothing, so the floor kicks in
+        contract
+            .set_transfer_fee(U256::from(100), U256::from(1), false)
+            .unwrap();
+        assert_eq!(contract.transfer_fee_for(U256::from(1)), U256::from(1));
+
+        vm.set_sender(holder);
+        assert!(contract.transfer_cupcake(friend, U256::from(1)).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(holder, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(9)
+        );
+        // The fee can't exceed the amount, so the friend gets nothing
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(friend, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(contract.total_supply(), U256::from(9));
+
+        vm.set_sender(owner);
+        let err: Vec<u8> = VendingMachineError::FeeTooHigh(FeeTooHigh {
+            feeBps: U256::from(10_001),
+            max: U256::from(FEE_DENOMINATOR),
+        })
+        .into();
+        assert_eq!(
+            contract.set_transfer_fee(U256::from(10_001), U256::ZERO, false),
+            Err(err)
+        );
+    }
+
+    #[test]
+    fn test_transfer_fee_on_every_path() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let holder = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let spender = address!("0x3333333333333333333333333333333333333333");
+        contract
+            .adjust_balance(holder, I256::try_from(100).unwrap())
+            .unwrap();
+        contract
+            .set_transfer_fee(U256::from(5_000), U256::ZERO, false)
+            .unwrap();
+        let balance = |contract: &VendingMachine, user| contract.effective_balance(user);
+
+        vm.set_sender(holder);
+        contract
+            .batch_transfer(vec![alice], vec![U256::from(40)])
+            .unwrap();
+        assert_eq!(balance(&contract, holder), U256::from(60));
+        assert_eq!(balance(&contract, alice), U256::from(20));
+
+        contract.approve(spender, U256::from(20)).unwrap();
+        vm.set_sender(spender);
+        assert!(contract.transfer_from(holder, bob, U256::from(20)).unwrap());
+        assert_eq!(balance(&contract, holder), U256::from(40));
+        assert_eq!(balance(&contract, bob), U256::from(10));
+        assert!(contract.allowance(holder, spender).is_zero());
+
+        // Both legs of a swap pay
+        vm.set_sender(alice);
+        contract.approve(holder, U256::from(10)).unwrap();
+        vm.set_sender(holder);
+        contract
+            .swap(alice, U256::from(10), U256::from(10))
+            .unwrap();
+        assert_eq!(balance(&contract, holder), U256::from(35));
+        assert_eq!(balance(&contract, alice), U256::from(15));
+        assert_eq!(contract.total_supply(), U256::from(60));
+    }
+
+    #[test]
+    fn test_transfer_fee_rounding() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let holder = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let friend = address!("0x1111111111111111111111111111111111111111");
+        contract
+            .adjust_balance(holder, I256::try_from(1_000).unwrap())
+            .unwrap();
+
+        // 2.5% of 150 is 3.75
+        contract
+            .set_transfer_fee(U256::from(250), U256::ZERO, false)
+            .unwrap();
+        assert_eq!(contract.transfer_fee_for(U256::from(150)), U256::from(3));
+        contract
+            .set_transfer_fee(U256::from(250), U256::ZERO, true)
+            .unwrap();
+        assert_eq!(contract.transfer_fee_for(U256::from(150)), U256::from(4));
+        // 2.5% of 100 is exactly 2.5, which rounds up
+        assert_eq!(contract.transfer_fee_for(U256::from(100)), U256::from(3));
+
+        vm.set_sender(holder);
+        assert!(contract.transfer_cupcake(friend, U256::from(150)).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(friend, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(146)
+        );
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(holder, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(850)
+        );
+    }
+
+    #[test]
+    fn test_seconds_since_last_claim() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(100);
+        assert_eq!(contract.seconds_since_last_claim(user).unwrap(), U256::ZERO);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(contract.seconds_since_last_claim(user).unwrap(), U256::ZERO);
+
+        vm.set_block_timestamp(103);
+        assert_eq!(
+            contract.seconds_since_last_claim(user).unwrap(),
+            U256::from(3)
+        );
+        // Keeps counting well past the cooldown
+        vm.set_block_timestamp(1_100);
+        assert_eq!(
+            contract.seconds_since_last_claim(user).unwrap(),
+            U256::from(1_000)
+        );
+        assert_eq!(contract.time_until_next_cupcake(user).unwrap(), U256::ZERO);
+    }
+
+    #[test]
+    fn test_on_cupcake_given_hook() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_grant_amount(U256::from(3)).unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        GIVEN_HOOK_CALLS.with(|calls| calls.borrow_mut().clear());
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        // A turned-away claim doesn't reach the hook
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        let calls = GIVEN_HOOK_CALLS.with(|calls| calls.borrow().clone());
+        assert_eq!(calls, vec![(user, U256::from(3))]);
+    }
+
+    #[test]
+    fn test_max_batch_size() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_max_batch_size(U256::from(2)).unwrap();
+        assert_eq!(contract.max_batch_size(), U256::from(2));
+        let a = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let b = address!("0x1111111111111111111111111111111111111111");
+        let c = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_block_timestamp(100);
+        assert_eq!(
+            contract.give_cupcakes_to(vec![a, b], false),
+            Ok(U256::from(2))
+        );
+        assert!(contract.sum_balances(vec![a, b]).is_ok());
+        assert!(contract.batch_next_eligible(vec![a, b]).is_ok());
+
+        let err: Vec<u8> = VendingMachineError::BatchTooLarge(BatchTooLarge {
+            length: U256::from(3),
+            max: U256::from(2),
+        })
+        .into();
+        let too_many = vec![a, b, c];
+        assert_eq!(
+            contract.give_cupcakes_to(too_many.clone(), false),
+            Err(err.clone())
+        );
+        assert_eq!(contract.sum_balances(too_many.clone()), Err(err.clone()));
+        assert_eq!(
+            contract.batch_next_eligible(too_many.clone()),
+            Err(err.clone())
+        );
+        assert_eq!(
+            contract.set_user_cooldowns(too_many.clone(), U256::from(1)),
+            Err(err.clone())
+        );
+        vm.set_sender(a);
+        assert_eq!(
+            contract.batch_transfer(too_many, vec![U256::ZERO; 3]),
+            Err(err)
+        );
+        assert_eq!(
+            contract.get_cupcake_balance_for(c, DEFAULT_FLAVOR).unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_emit_metrics() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let bob = address!("0x1111111111111111111111111111111111111111");
+        let carol = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(alice, DEFAULT_FLAVOR).unwrap());
+        assert!(contract.give_cupcake_to(bob, DEFAULT_FLAVOR).unwrap());
+        assert!(contract.give_cupcake_to(bob, U256::from(1)).unwrap());
+        // Bob hands his default cupcake to Carol but still holds another flavor
+        vm.set_sender(bob);
+        assert!(contract.transfer_cupcake(carol, U256::from(1)).unwrap());
+        // Alice's cupcake is burned, so she stops counting as a holder
+        vm.set_sender(contract.owner());
+        contract
+            .adjust_balance(alice, I256::try_from(-1).unwrap())
+            .unwrap();
+
+        vm.set_block_timestamp(200);
+        contract.emit_metrics();
+        let (topics, data) = vm.get_emitted_logs().last().unwrap().clone();
+        assert_eq!(topics[0], MetricsSnapshot::SIGNATURE_HASH);
+        let snapshot = MetricsSnapshot::decode_raw_log(topics, &data, true).unwrap();
+        assert_eq!(snapshot.totalDistributed, contract.total_distributed());
+        assert_eq!(snapshot.totalDistributed, U256::from(3));
+        assert_eq!(snapshot.totalSupply, contract.total_supply());
+        assert_eq!(snapshot.totalSupply, U256::from(2));
+        assert_eq!(snapshot.holders, U256::from(2));
+        assert_eq!(snapshot.timestamp, U256::from(200));
+    }
+
+    #[test]
+    fn test_auto_compound() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let plain = address!("0x1111111111111111111111111111111111111111");
+        let compounding = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_sender(compounding);
+        contract.enable_auto_compound(true).unwrap();
+        assert!(contract.is_auto_compound_enabled(compounding));
+        assert!(!contract.is_auto_compound_enabled(plain));
+
+        // Streaks of 1, 2 and 3: compounding adds 1 + 2 + 3 on top of the three cupcakes
+        for i in 1..=3 {
+            vm.set_block_timestamp(100 * i);
+            assert!(contract.give_default_cupcake_to(plain).unwrap());
+            assert!(contract.give_default_cupcake_to(compounding).unwrap());
+        }
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(plain).unwrap(),
+            U256::from(3)
+        );
+        assert_eq!(
+            contract
+                .get_default_cupcake_balance_for(compounding)
+                .unwrap(),
+            U256::from(9)
+        );
+
+        // Turning it off goes back to plain claims
+        contract.enable_auto_compound(false).unwrap();
+        vm.set_block_timestamp(400);
+        assert!(contract.give_default_cupcake_to(compounding).unwrap());
+        assert_eq!(
+            contract
+                .get_default_cupcake_balance_for(compounding)
+                .unwrap(),
+            U256::from(10)
+        );
+    }
+
+    #[test]
+    fn test_auto_compound_counts_against_caps() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0x2222222222222222222222222222222222222222");
+        contract.set_max_supply(U256::from(5)).unwrap();
+        vm.set_sender(user);
+        contract.enable_auto_compound(true).unwrap();
+
+        // 1 + 1, then 1 + 2: the bonus is distributed like the grant itself
+        for t in [100, 200] {
+            vm.set_block_timestamp(t);
+            assert!(contract.give_default_cupcake_to(user).unwrap());
+        }
+        assert_eq!(contract.total_distributed(), U256::from(5));
+        assert_eq!(contract.lifetime_received(user), U256::from(5));
+
+        // 1 + 3 no longer fits, and the preview says so
+        vm.set_block_timestamp(300);
+        assert_eq!(
+            contract.effective_grant_amount(user).unwrap(),
+            U256::from(4)
+        );
+        assert_eq!(contract.try_give(user).unwrap(), REASON_SOLD_OUT);
+        let err: Vec<u8> = VendingMachineError::SoldOut(SoldOut {
+            maxSupply: U256::from(5),
+        })
+        .into();
+        assert_eq!(contract.give_default_cupcake_to(user), Err(err));
+        assert_eq!(contract.total_supply(), U256::from(5));
+    }
+
+    #[test]
+    fn test_timelocked_cooldown_change() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_timelock_delay(U256::from(3600)).unwrap();
+
+        let err: Vec<u8> = VendingMachineError::NoPendingCooldown(NoPendingCooldown {}).into();
+        assert_eq!(contract.execute_cooldown(), Err(err));
+
+        vm.set_block_timestamp(1000);
+        let eta = contract.propose_cooldown(U256::from(60)).unwrap();
+        assert_eq!(eta, U256::from(4600));
+        assert_eq!(contract.pending_cooldown(), (U256::from(60), eta));
+
+        // Too early: the current cooldown stays in force
+        vm.set_block_timestamp(4599);
+        let err: Vec<u8> =
+            VendingMachineError::TimelockNotElapsed(TimelockNotElapsed { eta }).into();
+        assert_eq!(contract.execute_cooldown(), Err(err));
+        assert_eq!(contract.base_cooldown(), U256::from(COOLDOWN_SECONDS));
+
+        // Strangers can't execute it either
+        let stranger = address!("0x1111111111111111111111111111111111111111");
+        let owner = contract.owner();
+        vm.set_sender(stranger);
+        assert!(contract.execute_cooldown().is_err());
+        vm.set_sender(owner);
+
+        vm.set_block_timestamp(4600);
+        contract.execute_cooldown().unwrap();
+        assert_eq!(contract.base_cooldown(), U256::from(60));
+        assert_eq!(contract.pending_cooldown(), (U256::ZERO, U256::ZERO));
+        assert_eq!(
+            contract.get_effective_cooldown(stranger).unwrap(),
+            U256::from(60)
+        );
+    }
+
+    #[test]
+    fn test_am_i_owner() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        assert!(contract.am_i_owner());
+
+        vm.set_sender(address!("0x1111111111111111111111111111111111111111"));
+        assert!(!contract.am_i_owner());
+    }
+
+    #[test]
+    fn test_transfer_and_call() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let receiver = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        let data = vec![0xde, 0xad];
+        let calldata = IERC677Receiver::onTokenTransferCall {
+            from: user,
+            amount: U256::from(1),
+            data: data.clone().into(),
+        }
+        .abi_encode();
+
+        // The mocked revert only matches the expected hook call, proving it is made
+        vm.set_sender(user);
+        vm.mock_call(receiver, calldata.clone(), Err(vec![]));
+        let err: Vec<u8> =
+            VendingMachineError::TransferCallbackFailed(TransferCallbackFailed { receiver }).into();
+        assert_eq!(
+            contract.transfer_and_call(receiver, U256::from(1), data.clone()),
+            Err(err)
+        );
+
+        // TestVM doesn't roll back on revert, so start over with a fresh balance
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        vm.set_sender(user);
+        vm.mock_call(receiver, calldata, Ok(vec![]));
+        assert!(contract
+            .transfer_and_call(receiver, U256::from(1), data)
+            .unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(receiver).unwrap(),
+            U256::from(1)
+        );
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_admin_cooldown() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+
+        vm.set_block_timestamp(1000);
+        contract.set_admin_cooldown(U256::from(60)).unwrap();
+        assert_eq!(contract.admin_cooldown(), U256::from(60));
+
+        // Any config change within the cooldown is rejected, including the cooldown itself
+        vm.set_block_timestamp(1030);
+        let err: Vec<u8> = VendingMachineError::ConfigChangeTooSoon(ConfigChangeTooSoon {
+            nextAllowed: U256::from(1060),
+        })
+        .into();
+        assert_eq!(contract.set_daily_limit(U256::from(3)), Err(err.clone()));
+        assert_eq!(contract.set_admin_cooldown(U256::ZERO), Err(err));
+
+        // Emergency actions aren't throttled
+        contract.pause().unwrap();
+        contract.unpause().unwrap();
+
+        vm.set_block_timestamp(1060);
+        contract.set_daily_limit(U256::from(3)).unwrap();
+        assert_eq!(contract.daily_limit(), U256::from(3));
+        vm.set_block_timestamp(1061);
+        assert!(contract.set_grant_amount(U256::from(2)).is_err());
+    }
+
+    #[test]
+    fn test_read_raw_slot() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+
+        // Slot 2 packs `owner` in the low 20 bytes with `block_contracts` right above it
+        let slot = U256::from(2);
+        let owner_word = U256::from_be_slice(owner.as_slice());
+        assert_eq!(contract.read_raw_slot(slot).unwrap(), owner_word);
+        contract.set_block_contracts(true).unwrap();
+        assert_eq!(
+            contract.read_raw_slot(slot).unwrap(),
+            owner_word | (U256::from(1) << 160)
+        );
+
+        vm.set_sender(address!("0x1111111111111111111111111111111111111111"));
+        assert!(contract.read_raw_slot(slot).is_err());
+    }
+
+    #[test]
+    fn test_cooldown_message() {
+        assert_eq!(
+            cooldown_message(U256::from(5)),
+            "HTTP 429: Too Many Cupcakes (you must wait 5 more seconds before your next cupcake)"
+        );
+        assert_eq!(
+            cooldown_message(U256::from(1)),
+            "HTTP 429: Too Many Cupcakes (you must wait 1 more second before your next cupcake)"
+        );
+        assert_eq!(
+            cooldown_message(U256::from(3600)),
+            "HTTP 429: Too Many Cupcakes (you must wait 3600 more seconds before your next cupcake)"
+        );
+    }
+
+    #[test]
+    fn test_flavor_caps() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let vanilla = DEFAULT_FLAVOR;
+        let chocolate = U256::from(1);
+        contract
+            .set_flavor_max_supply(vanilla, U256::from(1))
+            .unwrap();
+        contract
+            .set_flavor_max_supply(chocolate, U256::from(2))
+            .unwrap();
+        assert_eq!(contract.flavor_remaining(U256::from(2)), U256::MAX);
+
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(alice, vanilla).unwrap());
+        assert_eq!(contract.flavor_remaining(vanilla), U256::ZERO);
+
+        // Vanilla is sold out, chocolate still has room
+        let err: Vec<u8> = VendingMachineError::FlavorSoldOut(FlavorSoldOut {
+            flavorId: vanilla,
+            maxSupply: U256::from(1),
+        })
+        .into();
+        assert_eq!(contract.give_cupcake_to(bob, vanilla), Err(err));
+        assert_eq!(contract.try_give(bob).unwrap(), REASON_SOLD_OUT);
+        assert!(contract.give_cupcake_to(alice, chocolate).unwrap());
+        assert!(contract.give_cupcake_to(bob, chocolate).unwrap());
+        assert_eq!(contract.flavor_total_supply(chocolate), U256::from(2));
+        assert_eq!(contract.flavor_remaining(chocolate), U256::ZERO);
+        vm.set_block_timestamp(200);
+        assert!(contract.give_cupcake_to(bob, chocolate).is_err());
+        assert_eq!(contract.flavor_total_supply(vanilla), U256::from(1));
+    }
+
+    #[test]
+    fn test_balance_decay() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        contract.set_grant_amount(U256::from(100)).unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+
+        // Balances held before decay was enabled only decay from that point on
+        vm.set_block_timestamp(1000);
+        contract.set_decay(true, U256::from(1)).unwrap();
+        vm.set_block_timestamp(1030);
+        assert_eq!(contract.effective_balance(alice), U256::from(70));
+        assert_eq!(
+            contract.flavor_balance(alice, DEFAULT_FLAVOR),
+            U256::from(100)
+        );
+        assert_eq!(contract.total_supply(), U256::from(100));
+
+        // The next mutation writes the decay to storage before moving anything
+        vm.set_sender(alice);
+        assert!(contract.transfer_cupcake(bob, U256::from(10)).unwrap());
+        assert_eq!(contract.my_balance().unwrap(), U256::from(60));
+        assert_eq!(contract.total_supply(), U256::from(70));
+        assert_eq!(
+            contract.flavor_balance(alice, DEFAULT_FLAVOR),
+            U256::from(60)
+        );
+
+        // Decay restarts from the last activity and floors at zero
+        vm.set_block_timestamp(1040);
+        assert_eq!(contract.effective_balance(alice), U256::from(50));
+        assert_eq!(contract.effective_balance(bob), U256::ZERO);
+        vm.set_block_timestamp(5000);
+        assert_eq!(contract.effective_balance(alice), U256::ZERO);
+
+        // Turning decay off shows the stored balance again
+        vm.set_block_timestamp(6000);
+        vm.set_sender(contract.owner());
+        contract.set_decay(false, U256::ZERO).unwrap();
+        assert_eq!(contract.effective_balance(alice), U256::from(60));
+    }
+
+    #[test]
+    fn test_contract_age() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        vm.set_block_timestamp(1000);
+        contract.init().unwrap();
+        assert_eq!(contract.contract_age().unwrap(), U256::ZERO);
+
+        vm.set_block_timestamp(1000 + SECONDS_PER_DAY);
+        assert_eq!(
+            contract.contract_age().unwrap(),
+            U256::from(SECONDS_PER_DAY)
+        );
+    }
+
+    #[test]
+    fn test_infinite_allowance() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let holder = vm.msg_sender();
+        let infinite = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let finite = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        contract.init().unwrap();
+        contract.set_grant_amount(U256::from(10)).unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(holder, DEFAULT_FLAVOR).unwrap());
+        contract.approve(infinite, U256::MAX).unwrap();
+        contract.approve(finite, U256::from(5)).unwrap();
+
+        vm.set_sender(infinite);
+        assert!(contract
+            .transfer_from(holder, infinite, U256::from(3))
+            .unwrap());
+        assert_eq!(contract.allowance(holder, infinite), U256::MAX);
+
+        vm.set_sender(finite);
+        assert!(contract
+            .transfer_from(holder, finite, U256::from(3))
+            .unwrap());
+        assert_eq!(contract.allowance(holder, finite), U256::from(2));
+    }
+
+    #[test]
+    fn test_claim_fee() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        contract.set_grant_amount(U256::from(100)).unwrap();
+        contract.set_claim_fee(U256::from(1_000)).unwrap();
+
+        // Nothing to charge on the first claim
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(100)
+        );
+
+        // 10% of 100 is burned, then 100 is added
+        vm.set_block_timestamp(200);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(190)
+        );
+
+        // 10% of 190 floors to 19
+        vm.set_block_timestamp(300);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(271)
+        );
+        assert_eq!(contract.total_supply(), U256::from(271));
+
+        let err: Vec<u8> = VendingMachineError::FeeTooHigh(FeeTooHigh {
+            feeBps: U256::from(10_001),
+            max: U256::from(FEE_DENOMINATOR),
+        })
+        .into();
+        assert_eq!(contract.set_claim_fee(U256::from(10_001)), Err(err));
+    }
+
+    #[test]
+    fn test_balance_difference() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let a = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let b = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        assert_eq!(
+            contract.balance_difference(a, b).unwrap(),
+            (true, U256::ZERO)
+        );
+
+        for _ in 0..3 {
+            vm.set_block_timestamp(vm.block_timestamp() + 6);
+            assert!(contract.give_cupcake_to(a, DEFAULT_FLAVOR).unwrap());
+        }
+        assert!(contract.give_cupcake_to(b, DEFAULT_FLAVOR).unwrap());
+
+        assert_eq!(
+            contract.balance_difference(a, b).unwrap(),
+            (true, U256::from(2))
+        );
+        assert_eq!(
+            contract.balance_difference(b, a).unwrap(),
+            (false, U256::from(2))
+        );
+    }
+
+    #[test]
+    fn test_total_supply_at() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+
+        vm.set_block_number(10);
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+        vm.set_block_number(20);
+        vm.set_block_timestamp(200);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        vm.set_block_number(30);
+        contract
+            .adjust_balance(bob, I256::try_from(-1).unwrap())
+            .unwrap();
+        vm.set_block_number(40);
+
+        assert_eq!(contract.total_supply_at(5).unwrap(), U256::ZERO);
+        // Both claims in block 10 land in one checkpoint
+        assert_eq!(contract.total_supply_at(10).unwrap(), U256::from(2));
+        assert_eq!(contract.total_supply_at(15).unwrap(), U256::from(2));
+        assert_eq!(contract.total_supply_at(20).unwrap(), U256::from(3));
+        assert_eq!(contract.total_supply_at(30).unwrap(), U256::from(2));
+        assert_eq!(
+            contract.total_supply_at(40).unwrap(),
+            contract.total_supply()
+        );
+
+        let err: Vec<u8> = VendingMachineError::FutureBlock(FutureBlock {
+            block: U256::from(41),
+            current: U256::from(40),
+        })
+        .into();
+        assert_eq!(contract.total_supply_at(41), Err(err));
+    }
+
+    #[test]
+    fn test_swap() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        contract.set_grant_amount(U256::from(10)).unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+
+        // Bob only approved 3, so asking for 4 fails
+        vm.set_sender(bob);
+        contract.approve(alice, U256::from(3)).unwrap();
+        vm.set_sender(alice);
+        let err: Vec<u8> = VendingMachineError::InsufficientAllowance(InsufficientAllowance {
+            spender: alice,
+            allowance: U256::from(3),
+            needed: U256::from(4),
+        })
+        .into();
+        assert_eq!(contract.swap(bob, U256::from(5), U256::from(4)), Err(err));
+        assert_eq!(contract.effective_balance(alice), U256::from(10));
+
+        contract.swap(bob, U256::from(5), U256::from(3)).unwrap();
+        assert_eq!(contract.effective_balance(alice), U256::from(8));
+        assert_eq!(contract.effective_balance(bob), U256::from(12));
+        assert_eq!(contract.allowance(bob, alice), U256::ZERO);
+    }
+
+    #[test]
+    fn test_projected_balance() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        contract.set_grant_amount(U256::from(3)).unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        let projected = contract.projected_balance(user, U256::from(7)).unwrap();
+        assert_eq!(projected, U256::from(24));
+        for day in 1..=7 {
+            vm.set_block_timestamp(100 + day * SECONDS_PER_DAY);
+            assert!(contract.give_default_cupcake_to(user).unwrap());
+        }
+        assert_eq!(contract.effective_balance(user), projected);
+
+        assert!(contract.projected_balance(user, U256::MAX).is_err());
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract
+            .set_circuit_breaker(U256::from(3), U256::from(60))
+            .unwrap();
+        let users = [
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+            address!("0x4444444444444444444444444444444444444444"),
+        ];
+
+        // Three claims spread over a minute are fine, and old ones age out of the window
+        for (i, user) in users.iter().take(3).enumerate() {
+            vm.set_block_timestamp(100 + 30 * i as u64);
+            assert!(contract.give_default_cupcake_to(*user).unwrap());
+        }
+        vm.set_block_timestamp(160);
+        assert!(contract.give_default_cupcake_to(users[3]).unwrap());
+
+        // A fourth claim within 60 seconds of the oldest recent one trips the breaker
+        vm.set_block_timestamp(161);
+        assert!(!contract.give_default_cupcake_to(users[0]).unwrap());
+        assert!(contract.paused());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(users[0]).unwrap(),
+            U256::from(1)
+        );
+        let (topics, _) = vm.get_emitted_logs().last().unwrap().clone();
+        assert_eq!(topics[0], CircuitBreakerTripped::SIGNATURE_HASH);
+        let err: Vec<u8> = VendingMachineError::ContractPaused(ContractPaused {}).into();
+        assert_eq!(contract.give_default_cupcake_to(users[1]), Err(err));
+
+        // Only the owner can resume, which starts the window afresh
+        vm.set_sender(users[0]);
+        assert!(contract.unpause().is_err());
+        vm.set_sender(contract.owner());
+        contract.unpause().unwrap();
+        assert!(contract.give_default_cupcake_to(users[1]).unwrap());
+    }
+
+    #[test]
+    fn test_circuit_breaker_mid_batch() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract
+            .set_circuit_breaker(U256::from(2), U256::from(60))
+            .unwrap();
+        let [a, b, c, d] = [
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+            address!("0x4444444444444444444444444444444444444444"),
+        ];
+
+        // The third grant trips the breaker; the batch keeps the first two and the pause
+        vm.set_block_timestamp(100);
+        assert_eq!(
+            contract.give_cupcakes_to(vec![a, b, c, d], false).unwrap(),
+            U256::from(2)
+        );
+        assert!(contract.paused());
+        assert!(contract
+            .get_default_cupcake_balance_for(c)
+            .unwrap()
+            .is_zero());
+        assert!(contract
+            .get_default_cupcake_balance_for(d)
+            .unwrap()
+            .is_zero());
+
+        // Strict mode stops the same way instead of reporting an ineligible recipient
+        contract.unpause().unwrap();
+        vm.set_block_timestamp(200);
+        assert_eq!(
+            contract.give_cupcakes_to(vec![c, d, a], true).unwrap(),
+            U256::from(2)
+        );
+        assert!(contract.paused());
+
+        contract.unpause().unwrap();
+        vm.set_block_timestamp(300);
+        assert_eq!(
+            contract.give_cupcakes_detailed(vec![a, b, c, d]).unwrap(),
+            vec![true, true, false, false]
+        );
+        assert!(contract.paused());
+
+        // try_give reports the trip instead of claiming success
+        contract.unpause().unwrap();
+        vm.set_block_timestamp(400);
+        assert_eq!(contract.try_give(a).unwrap(), REASON_OK);
+        assert_eq!(contract.try_give(b).unwrap(), REASON_OK);
+        assert_eq!(contract.try_give(c).unwrap(), REASON_CIRCUIT_BREAKER);
+        assert!(contract.paused());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(c).unwrap(),
+            U256::from(1)
+        );
+    }
+
+    #[test]
+    fn test_list_flavors() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let sprinkles = U256::from(7);
+        assert!(contract.list_flavors().unwrap().is_empty());
+
+        contract
+            .set_flavor_max_supply(sprinkles, U256::from(10))
+            .unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert!(contract.give_cupcake_to(user, sprinkles).unwrap());
+        vm.set_block_timestamp(200);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+
+        assert_eq!(
+            contract.list_flavors().unwrap(),
+            vec![sprinkles, DEFAULT_FLAVOR]
+        );
+    }
+
+    #[test]
+    fn test_zero_address_fallback() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let kiosk_user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        vm.set_block_timestamp(100);
+
+        let err: Vec<u8> = VendingMachineError::ZeroRecipient(ZeroRecipient {}).into();
+        assert_eq!(contract.give_default_cupcake_to(Address::ZERO), Err(err));
+        assert_eq!(
+            contract.try_give(Address::ZERO).unwrap(),
+            REASON_ZERO_RECIPIENT
+        );
+
+        contract.set_zero_address_fallback(true).unwrap();
+        vm.set_sender(kiosk_user);
+        assert!(contract.give_default_cupcake_to(Address::ZERO).unwrap());
+        assert_eq!(contract.my_balance().unwrap(), U256::from(1));
+        assert_eq!(
+            contract
+                .get_default_cupcake_balance_for(Address::ZERO)
+                .unwrap(),
+            U256::ZERO
+        );
+
+        // Reason codes and batch pre-checks look at the caller, who is cooling down
+        assert_eq!(contract.try_give(Address::ZERO).unwrap(), REASON_COOLDOWN);
+        let err: Vec<u8> =
+            VendingMachineError::IneligibleRecipient(IneligibleRecipient { user: kiosk_user })
+                .into();
+        assert_eq!(
+            contract.give_cupcakes_to(vec![Address::ZERO], true),
+            Err(err)
+        );
+        vm.set_block_timestamp(200);
+        assert_eq!(
+            contract
+                .give_cupcakes_detailed(vec![Address::ZERO])
+                .unwrap(),
+            vec![true]
+        );
+        assert_eq!(contract.my_balance().unwrap(), U256::from(2));
+    }
+
+    #[test]
+    fn test_history_root() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(contract.get_history_root(user), B256::ZERO);
+
+        let replay = |root: B256, timestamp: u64, amount: u64| {
+            let mut preimage = root.to_vec();
+            preimage.extend_from_slice(&U256::from(timestamp).to_be_bytes::<32>());
+            preimage.extend_from_slice(&U256::from(amount).to_be_bytes::<32>());
+            keccak(preimage)
+        };
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        let first = replay(B256::ZERO, 100, 1);
+        assert_eq!(contract.get_history_root(user), first);
+
+        contract.set_grant_amount(U256::from(2)).unwrap();
+        vm.set_block_timestamp(200);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(contract.get_history_root(user), replay(first, 200, 2));
+    }
+
+    #[test]
+    fn test_set_distribution_time() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert!(!contract.give_default_cupcake_to(user).unwrap());
+
+        // Zero lets the user claim again straight away
+        contract.set_distribution_time(user, U256::ZERO).unwrap();
+        let (topics, data) = vm.get_emitted_logs().last().unwrap().clone();
+        let event = DistributionTimeSet::decode_raw_log(topics, &data, true).unwrap();
+        assert_eq!(event.user, user);
+        assert_eq!(event.previous, U256::from(100));
+        assert_eq!(event.timestamp, U256::ZERO);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        // A future time pushes the next claim out to a full cooldown after it
+        contract
+            .set_distribution_time(user, U256::from(1000))
+            .unwrap();
+        vm.set_block_timestamp(500);
+        assert!(!contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.time_until_next_cupcake(user).unwrap(),
+            U256::from(COOLDOWN_SECONDS)
+        );
+        vm.set_block_timestamp(1000 + COOLDOWN_SECONDS);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        vm.set_sender(user);
+        assert!(contract.set_distribution_time(user, U256::ZERO).is_err());
+    }
+
+    #[test]
+    fn test_get_tier() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(contract.get_tier(user).unwrap(), 0);
+
+        let err: Vec<u8> =
+            VendingMachineError::InvalidTierThresholds(InvalidTierThresholds {}).into();
+        assert_eq!(
+            contract.set_tier_thresholds(vec![U256::from(5), U256::from(5)]),
+            Err(err)
+        );
+        contract
+            .set_tier_thresholds(vec![U256::from(1), U256::from(5), U256::from(10)])
+            .unwrap();
+        assert_eq!(contract.get_tier(user).unwrap(), 0);
+
+        for (balance, tier) in [(1, 1), (4, 1), (5, 2), (9, 2), (10, 3), (50, 3)] {
+            let current = contract.effective_balance(user);
+            let delta = I256::try_from(balance).unwrap() - I256::try_from(current).unwrap();
+            contract.adjust_balance(user, delta).unwrap();
+            assert_eq!(contract.get_tier(user).unwrap(), tier, "balance {balance}");
+        }
+
+        let newcomer = address!("0x1111111111111111111111111111111111111111");
+        assert!(!contract.same_tier(user, newcomer).unwrap());
+        contract
+            .adjust_balance(newcomer, I256::try_from(12).unwrap())
+            .unwrap();
+        assert!(contract.same_tier(user, newcomer).unwrap());
+    }
+
+    #[test]
+    fn test_give_cupcakes_detailed() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let fresh = address!("0x1111111111111111111111111111111111111111");
+        let cooling = address!("0x2222222222222222222222222222222222222222");
+        let blocked = address!("0x3333333333333333333333333333333333333333");
+        contract.set_blacklisted(blocked, true).unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(cooling).unwrap());
+
+        let results = contract
+            .give_cupcakes_detailed(vec![cooling, fresh, blocked, fresh])
+            .unwrap();
+        // The second `fresh` entry is cooling down after the first one's grant
+        assert_eq!(results, vec![false, true, false, false]);
+        assert_eq!(contract.effective_balance(fresh), U256::from(1));
+        assert_eq!(contract.effective_balance(cooling), U256::from(1));
+    }
+
+    #[test]
+    fn test_give_cupcakes_detailed_mixed_reasons() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let capped = address!("0x1111111111111111111111111111111111111111");
+        let newcomer = address!("0x2222222222222222222222222222222222222222");
+        let veteran = address!("0x3333333333333333333333333333333333333333");
+        contract.set_daily_limit(U256::from(1)).unwrap();
+        contract
+            .set_cooldown_waived_until(U256::from(1_000))
+            .unwrap();
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(capped).unwrap());
+        contract.set_min_account_age(U256::from(60)).unwrap();
+        assert_eq!(contract.try_give(veteran).unwrap(), REASON_TOO_NEW);
+
+        // Daily limit and account age are reported as skips, not reverts
+        vm.set_block_timestamp(200);
+        assert_eq!(
+            contract
+                .give_cupcakes_detailed(vec![capped, newcomer, veteran])
+                .unwrap(),
+            vec![false, false, true]
+        );
+
+        // The newcomer's first sighting was recorded along the way
+        vm.set_block_timestamp(260);
+        assert_eq!(
+            contract.give_cupcakes_detailed(vec![newcomer]).unwrap(),
+            vec![true]
+        );
+    }
+
+    #[test]
+    fn test_burns_restore_quota() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        contract.set_lifetime_cap(U256::from(1)).unwrap();
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        // By default eating doesn't give the quota back
+        vm.set_sender(user);
+        contract.eat_cupcake(U256::from(1)).unwrap();
+        assert_eq!(contract.effective_balance(user), U256::ZERO);
+        vm.set_block_timestamp(200);
+        let err: Vec<u8> = VendingMachineError::LifetimeCapReached(LifetimeCapReached {
+            user,
+            cap: U256::from(1),
+        })
+        .into();
+        assert_eq!(contract.give_default_cupcake_to(user), Err(err));
+        assert_eq!(contract.try_give(user).unwrap(), REASON_LIFETIME_CAP);
+
+        // With the flag on, a fresh user can eat and claim again
+        let other = address!("0x1111111111111111111111111111111111111111");
+        vm.set_sender(contract.owner());
+        contract.set_burns_restore_quota(true).unwrap();
+        assert!(contract.give_default_cupcake_to(other).unwrap());
+        vm.set_sender(other);
+        contract.eat_cupcake(U256::from(1)).unwrap();
+        assert_eq!(contract.lifetime_received(other), U256::ZERO);
+        vm.set_block_timestamp(300);
+        assert!(contract.give_default_cupcake_to(other).unwrap());
+        assert_eq!(contract.effective_balance(other), U256::from(1));
+    }
+
+    #[test]
+    fn test_domain_separator() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+
+        let type_hash = keccak(
+            "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)",
+        );
+        let expected = keccak(
+            [
+                type_hash.as_slice(),
+                keccak("VendingMachine").as_slice(),
+                keccak("1").as_slice(),
+                &U256::from(vm.chain_id()).to_be_bytes::<32>(),
+                &U256::from_be_slice(vm.contract_address().as_slice()).to_be_bytes::<32>(),
+            ]
+            .concat(),
+        );
+        assert_eq!(contract.domain_separator(), expected);
+        assert_eq!(contract.cached_domain_separator.get(), expected);
+
+        // A cache recorded under another chain id is ignored
+        contract
+            .cached_domain_separator
+            .set(B256::repeat_byte(0xab));
+        contract.cached_chain_id.set(U256::from(1));
+        assert_eq!(contract.domain_separator(), expected);
+    }
+
+    #[test]
+    fn test_claim_complexity() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(
+            contract.claim_complexity(user).unwrap(),
+            COMPLEXITY_FIRST_CLAIM
+        );
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.claim_complexity(user).unwrap(),
+            COMPLEXITY_REPEAT_CLAIM
+        );
+
+        vm.set_sender(user);
+        contract.enable_auto_compound(true).unwrap();
+        assert_eq!(
+            contract.claim_complexity(user).unwrap(),
+            COMPLEXITY_BONUS_CLAIM
+        );
+    }
+
+    #[test]
+    fn test_required_stake() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let required = U256::from(1_000);
+        contract.set_required_stake(required).unwrap();
+        vm.set_block_timestamp(100);
+
+        let err: Vec<u8> = VendingMachineError::StakeTooLow(StakeTooLow {
+            user,
+            stake: U256::ZERO,
+            required,
+        })
+        .into();
+        assert_eq!(contract.give_default_cupcake_to(user), Err(err));
+        assert_eq!(contract.try_give(user).unwrap(), REASON_STAKE_TOO_LOW);
+
+        vm.set_sender(user);
+        vm.set_value(required);
+        assert_eq!(contract.stake().unwrap(), required);
+        vm.set_value(U256::ZERO);
+        assert_eq!(contract.stake_of(user), required);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        // The contract holds the stake, so it can hand part of it back
+        vm.set_balance(vm.contract_address(), required);
+        assert_eq!(contract.unstake(U256::from(1)).unwrap(), U256::from(999));
+        assert_eq!(vm.balance(user), U256::from(1));
+        vm.set_block_timestamp(200);
+        assert!(contract.give_default_cupcake_to(user).is_err());
+
+        assert!(contract.unstake(required).is_err());
+    }
+
+    #[test]
+    fn test_block_claim_limit() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_max_claims_per_block(U256::from(2)).unwrap();
+        let users = [
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+        ];
+        vm.set_block_timestamp(100);
+        vm.set_block_number(10);
+        assert!(contract.give_default_cupcake_to(users[0]).unwrap());
+        assert!(contract.give_default_cupcake_to(users[1]).unwrap());
+
+        let err: Vec<u8> = VendingMachineError::BlockClaimLimit(BlockClaimLimit {
+            blockNumber: U256::from(10),
+            max: U256::from(2),
+        })
+        .into();
+        assert_eq!(contract.give_default_cupcake_to(users[2]), Err(err));
+
+        // The quota resets with the next block
+        vm.set_block_number(11);
+        assert!(contract.give_default_cupcake_to(users[2]).unwrap());
+    }
+
+    #[test]
+    fn test_explain_cooldown() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        vm.set_block_timestamp(100);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let vip = address!("0x1111111111111111111111111111111111111111");
+        let base = U256::from(COOLDOWN_SECONDS);
+        assert_eq!(
+            contract.explain_cooldown(user).unwrap(),
+            (base, COOLDOWN_RULE_GLOBAL)
+        );
+
+        contract
+            .set_cooldown_growth(U256::from(10), U256::ZERO)
+            .unwrap();
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.explain_cooldown(user).unwrap(),
+            (base + U256::from(10), COOLDOWN_RULE_BALANCE_SCALED)
+        );
+
+        // A max below the base caps everyone, but only holders are balance-scaled
+        contract
+            .set_cooldown_growth(U256::from(10), U256::from(2))
+            .unwrap();
+        assert_eq!(
+            contract.explain_cooldown(vip).unwrap(),
+            (U256::from(2), COOLDOWN_RULE_GLOBAL)
+        );
+        assert_eq!(
+            contract.explain_cooldown(user).unwrap(),
+            (U256::from(2), COOLDOWN_RULE_BALANCE_SCALED)
+        );
+        contract
+            .set_user_cooldowns(vec![vip], U256::from(1))
+            .unwrap();
+        assert_eq!(
+            contract.explain_cooldown(vip).unwrap(),
+            (U256::from(1), COOLDOWN_RULE_OVERRIDE)
+        );
+
+        // The grace period beats any per-user rule, and a waiver beats the grace period
+        contract.set_grace_period(U256::from(1_000)).unwrap();
+        assert_eq!(
+            contract.explain_cooldown(vip).unwrap(),
+            (U256::ZERO, COOLDOWN_RULE_EXEMPT)
+        );
+        contract.set_cooldown_waived_until(U256::from(500)).unwrap();
+        assert_eq!(
+            contract.explain_cooldown(user).unwrap(),
+            (U256::ZERO, COOLDOWN_RULE_WAIVED)
+        );
+    }
+
+    #[test]
+    fn test_import_from_legacy() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let legacy = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let carol = address!("0x3333333333333333333333333333333333333333");
+        let mock_balance = |user: Address, balance: u64| {
+            vm.mock_static_call(
+                legacy,
+                ILegacyVendingMachine::getCupcakeBalanceForCall { userAddress: user }.abi_encode(),
+                Ok(
+                    ILegacyVendingMachine::getCupcakeBalanceForCall::abi_encode_returns(&(
+                        U256::from(balance),
+                    )),
+                ),
+            );
+        };
+        mock_balance(alice, 4);
+        mock_balance(bob, 7);
+        mock_balance(carol, 2);
+
+        // Carol already has a balance here, so her legacy one is not added on top
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(carol).unwrap());
+
+        let imported = contract
+            .import_from_legacy(legacy, vec![alice, bob, carol])
+            .unwrap();
+        assert_eq!(imported, U256::from(11));
+        assert_eq!(contract.effective_balance(alice), U256::from(4));
+        assert_eq!(contract.effective_balance(bob), U256::from(7));
+        assert_eq!(contract.effective_balance(carol), U256::from(1));
+        assert_eq!(contract.total_supply(), U256::from(12));
+
+        // Running the same import again is a no-op
+        assert_eq!(
+            contract
+                .import_from_legacy(legacy, vec![alice, bob])
+                .unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[test]
+    fn test_grant_halving() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        vm.set_block_timestamp(1_000);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        contract.set_grant_amount(U256::from(8)).unwrap();
+        contract
+            .set_halving(U256::from(SECONDS_PER_DAY), U256::from(3))
+            .unwrap();
+        assert_eq!(contract.current_grant_amount(), U256::from(8));
+
+        vm.set_block_timestamp(1_000 + SECONDS_PER_DAY);
+        assert_eq!(contract.current_grant_amount(), U256::from(4));
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(contract.effective_balance(user), U256::from(4));
+
+        // A second halving would give 2, but the floor keeps it at 3
+        vm.set_block_timestamp(1_000 + 2 * SECONDS_PER_DAY);
+        assert_eq!(contract.current_grant_amount(), U256::from(3));
+        vm.set_block_timestamp(1_000 + 300 * SECONDS_PER_DAY);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(contract.effective_balance(user), U256::from(7));
+    }
+
+    #[test]
+    fn test_claims_until_sold_out() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        assert_eq!(contract.claims_until_sold_out().unwrap(), U256::MAX);
+
+        contract.set_max_supply(U256::from(10)).unwrap();
+        contract.set_grant_amount(U256::from(3)).unwrap();
+        assert_eq!(contract.claims_until_sold_out().unwrap(), U256::from(3));
+
+        let users = [
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+        ];
+        vm.set_block_timestamp(100);
+        for (claimed, user) in users.iter().enumerate() {
+            assert!(contract.give_default_cupcake_to(*user).unwrap());
+            // 10 - 3 * claims left, divided by 3 and rounded down
+            let left = (10 - 3 * (claimed as u64 + 1)) / 3;
+            assert_eq!(contract.claims_until_sold_out().unwrap(), U256::from(left));
+        }
+
+        // One cupcake is left, which is not enough for a full grant of 3
+        assert_eq!(contract.claims_until_sold_out().unwrap(), U256::ZERO);
+        contract.set_grant_amount(U256::from(1)).unwrap();
+        assert_eq!(contract.claims_until_sold_out().unwrap(), U256::from(1));
+
+        // Once halving has taken the grant to zero there is nothing left to count down
+        contract.set_halving(U256::from(1), U256::ZERO).unwrap();
+        vm.set_block_timestamp(200);
+        assert_eq!(contract.current_grant_amount(), U256::ZERO);
+        assert_eq!(contract.claims_until_sold_out().unwrap(), U256::MAX);
+    }
+
+    #[test]
+    fn test_anti_circumvention() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let carol = address!("0x3333333333333333333333333333333333333333");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+
+        // Without the flag Bob can claim right after receiving Alice's cupcake
+        vm.set_sender(alice);
+        assert!(contract.transfer_cupcake(bob, U256::from(1)).unwrap());
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+
+        vm.set_sender(contract.owner());
+        contract.set_anti_circumvention(true).unwrap();
+        vm.set_block_timestamp(200);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        vm.set_sender(alice);
+        assert!(contract.transfer_cupcake(carol, U256::from(1)).unwrap());
+        assert!(!contract.give_default_cupcake_to(carol).unwrap());
+        assert_eq!(
+            contract.time_until_next_cupcake(carol).unwrap(),
+            U256::from(COOLDOWN_SECONDS)
+        );
+
+        vm.set_block_timestamp(200 + COOLDOWN_SECONDS);
+        assert!(contract.give_default_cupcake_to(carol).unwrap());
+    }
+
+    #[test]
+    fn test_packed_user_state() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(contract.packed_user_state(user).unwrap(), U256::ZERO);
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        let packed = contract.packed_user_state(user).unwrap();
+        let balance = packed & U256::from(u128::MAX);
+        let last = packed >> 128;
+        assert_eq!(balance, U256::from(2));
+        assert_eq!(last, U256::from(100 + COOLDOWN_SECONDS));
+    }
+
+    #[test]
+    fn test_set_blacklisted_many() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let users = vec![
+            address!("0x1111111111111111111111111111111111111111"),
+            address!("0x2222222222222222222222222222222222222222"),
+            address!("0x3333333333333333333333333333333333333333"),
+        ];
+        let other = address!("0x4444444444444444444444444444444444444444");
+        vm.set_block_timestamp(100);
+
+        contract.set_blacklisted_many(users.clone(), true).unwrap();
+        let (topics, data) = vm.get_emitted_logs().last().unwrap().clone();
+        let event = BatchBlacklistUpdated::decode_raw_log(topics, &data, true).unwrap();
+        assert_eq!(event.count, U256::from(3));
+        assert!(event.blocked);
+
+        for user in &users {
+            let err: Vec<u8> = VendingMachineError::Blacklisted(Blacklisted { user: *user }).into();
+            assert_eq!(contract.give_default_cupcake_to(*user), Err(err));
+        }
+        assert!(contract.give_default_cupcake_to(other).unwrap());
+
+        contract.set_blacklisted_many(users.clone(), false).unwrap();
+        for user in &users {
+            assert!(contract.give_default_cupcake_to(*user).unwrap());
+        }
+
+        // The batch cap applies
+        contract.set_max_batch_size(U256::from(2)).unwrap();
+        let err: Vec<u8> = VendingMachineError::BatchTooLarge(BatchTooLarge {
+            length: U256::from(3),
+            max: U256::from(2),
+        })
+        .into();
+        assert_eq!(contract.set_blacklisted_many(users.clone(), true), Err(err));
+        assert!(!contract.is_blacklisted(users[0]));
+
+        // Only the owner can sweep
+        vm.set_sender(other);
+        assert!(contract.set_blacklisted_many(users, true).is_err());
+    }
+
+    #[test]
+    fn test_cooldown_units() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        assert_eq!(
+            contract.get_cooldown_display(),
+            (U256::from(COOLDOWN_SECONDS), COOLDOWN_UNIT_SECONDS)
+        );
+
+        let err: Vec<u8> =
+            VendingMachineError::InvalidCooldownUnit(InvalidCooldownUnit { unit: 3 }).into();
+        assert_eq!(
+            contract.propose_cooldown_in_units(U256::from(5), 3),
+            Err(err)
+        );
+
+        // No timelock delay, so the proposal is executable straight away
+        vm.set_block_timestamp(1_000);
+        contract
+            .propose_cooldown_in_units(U256::from(5), COOLDOWN_UNIT_MINUTES)
+            .unwrap();
+        contract.execute_cooldown().unwrap();
+        assert_eq!(
+            contract.get_cooldown_display(),
+            (U256::from(5), COOLDOWN_UNIT_MINUTES)
+        );
+        assert_eq!(contract.base_cooldown(), U256::from(300));
+
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        vm.set_block_timestamp(1_000 + 4 * 60);
+        assert!(!contract.give_default_cupcake_to(user).unwrap());
+        vm.set_block_timestamp(1_000 + 5 * 60);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+    }
+
+    #[test]
+    fn test_first_claim_bonus() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert!(!contract.would_get_first_bonus(user));
+
+        contract.set_first_claim_bonus(U256::from(10)).unwrap();
+        assert!(contract.would_get_first_bonus(user));
+        assert_eq!(
+            contract.effective_grant_amount(user).unwrap(),
+            U256::from(11)
+        );
+        assert_eq!(
+            contract.projected_balance(user, U256::from(3)).unwrap(),
+            U256::from(13)
+        );
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(11)
+        );
+        assert!(!contract.would_get_first_bonus(user));
+
+        // Clearing the distribution time doesn't make the bonus due again
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        contract.clear_distribution_time(user).unwrap();
+        assert!(!contract.would_get_first_bonus(user));
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(12)
+        );
+    }
+
+    #[test]
+    fn test_recover_erc20() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let treasury = address!("0x1111111111111111111111111111111111111111");
+        let stray = address!("0x2222222222222222222222222222222222222222");
+        let reward = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
+        let transfer = |token: Address, amount: u64| {
+            vm.mock_call(
+                token,
+                IERC20::transferCall {
+                    to: treasury,
+                    amount: U256::from(amount),
+                }
+                .abi_encode(),
+                Ok(IERC20::transferCall::abi_encode_returns(&(true,))),
+            );
+        };
+
+        // Any stray token can be recovered in full
+        transfer(stray, 500);
+        contract
+            .recover_erc20(stray, treasury, U256::from(500))
+            .unwrap();
+
+        // 10 claims left at 5 reward tokens each keeps 50 of the 80 held back
+        contract.set_reward_token(reward, U256::from(5)).unwrap();
+        contract.set_max_supply(U256::from(10)).unwrap();
+        vm.mock_static_call(
+            reward,
+            IERC20::balanceOfCall {
+                account: vm.contract_address(),
+            }
+            .abi_encode(),
+            Ok(IERC20::balanceOfCall::abi_encode_returns(
+                &(U256::from(80),),
+            )),
+        );
+        let err: Vec<u8> = VendingMachineError::RewardTokenReserved(RewardTokenReserved {
+            token: reward,
+            available: U256::from(30),
+        })
+        .into();
+        assert_eq!(
+            contract.recover_erc20(reward, treasury, U256::from(31)),
+            Err(err)
+        );
+        transfer(reward, 30);
+        contract
+            .recover_erc20(reward, treasury, U256::from(30))
+            .unwrap();
+
+        vm.set_sender(treasury);
+        assert!(contract
+            .recover_erc20(stray, treasury, U256::from(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_display_cap() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_grant_amount(U256::from(150)).unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(150)
+        );
+
+        contract.set_display_cap(U256::from(99)).unwrap();
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(99)
+        );
+        assert_eq!(contract.effective_balance(user), U256::from(150));
+
+        // Accounting still uses the true balance
+        let other = address!("0x1111111111111111111111111111111111111111");
+        vm.set_sender(user);
+        assert!(contract.transfer_cupcake(other, U256::from(120)).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(30)
+        );
+
+        vm.set_sender(contract.owner());
+        contract.set_display_cap(U256::ZERO).unwrap();
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(other).unwrap(),
+            U256::from(120)
+        );
+    }
+
+    #[test]
+    fn test_raw_balance_of() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_grant_amount(U256::from(100)).unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(contract.raw_balance_of(user).unwrap(), U256::from(100));
+
+        contract.set_display_cap(U256::from(99)).unwrap();
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(99)
+        );
+        assert_eq!(contract.raw_balance_of(user).unwrap(), U256::from(100));
+
+        // Pending decay shows in the getter but not in the stored value
+        contract.set_display_cap(U256::ZERO).unwrap();
+        contract.set_decay(true, U256::from(2)).unwrap();
+        vm.set_block_timestamp(110);
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(80)
+        );
+        assert_eq!(contract.raw_balance_of(user).unwrap(), U256::from(100));
     }
-}
 
-#[public]
-impl VendingMachine {
-    pub fn give_cupcake_to(&mut self, user_address: Address) -> Result<bool, Vec<u8>> {
-        // Get the last distribution time for the user.
-        let last_distribution = self.cupcake_distribution_times.get(user_address);
-        // Calculate the earliest next time the user can receive a cupcake.
-        let five_seconds_from_last_distribution = last_distribution + U256::from(5);
-
-        // Get the current block timestamp using the VM pattern
-        let current_time = self.vm().block_timestamp();
-        // Check if the user can receive a cupcake.
-        let user_can_receive_cupcake =
-            five_seconds_from_last_distribution <= U256::from(current_time);
-
-        if user_can_receive_cupcake {
-            // Increment the user's cupcake balance.
-            let mut balance_accessor = self.cupcake_balances.setter(user_address);
-            let balance = balance_accessor.get() + U256::from(1);
-            balance_accessor.set(balance);
-
-            // Get current timestamp using the VM pattern BEFORE creating the mutable borrow
-            let new_distribution_time = self.vm().block_timestamp();
-
-            // Update the distribution time to the current time.
-            let mut time_accessor = self.cupcake_distribution_times.setter(user_address);
-            time_accessor.set(U256::from(new_distribution_time));
-            return Ok(true);
-        } else {
-            // User must wait before receiving another cupcake.
-            console!(
-                "HTTP 429: Too Many Cupcakes (you must wait at least 5 seconds between cupcakes)"
+    #[test]
+    fn test_cooldown_jitter() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let base = U256::from(COOLDOWN_SECONDS);
+        assert_eq!(contract.get_effective_cooldown(alice).unwrap(), base);
+        assert_eq!(contract.get_effective_cooldown(bob).unwrap(), base);
+
+        let range = U256::from(1_000);
+        contract.set_jitter_range(range).unwrap();
+        let alice_cooldown = contract.get_effective_cooldown(alice).unwrap();
+        let bob_cooldown = contract.get_effective_cooldown(bob).unwrap();
+        assert_ne!(alice_cooldown, bob_cooldown);
+        for (user, cooldown) in [(alice, alice_cooldown), (bob, bob_cooldown)] {
+            let jitter = U256::from_be_bytes(keccak(user).0) % range;
+            assert_eq!(cooldown, base + jitter);
+            assert_eq!(
+                contract.explain_cooldown(user).unwrap(),
+                (cooldown, COOLDOWN_RULE_GLOBAL)
             );
-            return Ok(false);
         }
+
+        // The jitter is part of the cooldown claims are checked against
+        vm.set_block_timestamp(10_000);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        let ready = 10_000 + alice_cooldown.to::<u64>();
+        vm.set_block_timestamp(ready - 1);
+        assert!(!contract.give_default_cupcake_to(alice).unwrap());
+        vm.set_block_timestamp(ready);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
     }
-    pub fn get_cupcake_balance_for(&self, user_address: Address) -> Result<U256, Vec<u8>> {
-        Ok(self.cupcake_balances.get(user_address))
+
+    #[test]
+    fn test_admin_action_count() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        assert_eq!(contract.get_admin_action_count(), U256::ZERO);
+
+        contract.set_grant_amount(U256::from(2)).unwrap();
+        contract.set_blacklisted(user, true).unwrap();
+        contract.pause().unwrap();
+        contract.unpause().unwrap();
+        assert_eq!(contract.get_admin_action_count(), U256::from(4));
+
+        // Reads and rejected callers leave it alone
+        contract.read_raw_slot(U256::ZERO).unwrap();
+        vm.set_sender(user);
+        assert!(contract.pause().is_err());
+        assert_eq!(contract.get_admin_action_count(), U256::from(4));
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use alloy_primitives::address;
-    use stylus_sdk::testing::*;
+    #[test]
+    fn test_reject_self_transfers() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+
+        // Allowed by default, leaving the balance as it was
+        vm.set_sender(user);
+        assert!(contract.transfer_cupcake(user, U256::from(1)).unwrap());
+        assert_eq!(contract.my_balance().unwrap(), U256::from(1));
+
+        vm.set_sender(owner);
+        contract.set_reject_self_transfers(true).unwrap();
+        vm.set_sender(user);
+        let logs_before = vm.get_emitted_logs().len();
+        let err: Vec<u8> = VendingMachineError::SelfTransfer(SelfTransfer { user }).into();
+        assert_eq!(contract.transfer_cupcake(user, U256::from(1)), Err(err));
+        assert_eq!(vm.get_emitted_logs().len(), logs_before);
+        assert_eq!(contract.my_balance().unwrap(), U256::from(1));
+
+        // Transfers to anyone else are unaffected
+        assert!(contract.transfer_cupcake(owner, U256::from(1)).unwrap());
+    }
 
     #[test]
-    fn test_give_cupcake_to() {
+    fn test_top_holder() {
         let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let carol = address!("0x3333333333333333333333333333333333333333");
+        assert_eq!(contract.top_holder().unwrap(), (Address::ZERO, U256::ZERO));
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+        assert!(contract.give_default_cupcake_to(carol).unwrap());
+
+        // A three-way tie goes to the earliest holder
+        assert_eq!(contract.top_holder().unwrap(), (alice, U256::from(1)));
+
+        vm.set_block_timestamp(100 + COOLDOWN_SECONDS);
+        assert!(contract.give_default_cupcake_to(carol).unwrap());
+        assert_eq!(contract.top_holder().unwrap(), (carol, U256::from(2)));
 
+        // Bob catches up by receiving Alice's cupcake, then it's a tie Bob wins on seniority
+        vm.set_sender(alice);
+        assert!(contract.transfer_cupcake(bob, U256::from(1)).unwrap());
+        assert_eq!(contract.top_holder().unwrap(), (bob, U256::from(2)));
+    }
+
+    #[test]
+    fn test_dead_man_switch() {
+        let vm = TestVM::default();
+        vm.set_block_timestamp(1_000);
         let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_dead_man_timeout(U256::from(100)).unwrap();
+        assert!(contract.is_admin_active());
+
+        // Any admin call resets the clock
+        vm.set_block_timestamp(1_100);
+        contract.pause().unwrap();
+        vm.set_block_timestamp(1_200);
+        assert!(contract.is_admin_active());
+        contract.unpause().unwrap();
+        contract.pause().unwrap();
+
+        vm.set_block_timestamp(1_301);
+        assert!(!contract.is_admin_active());
+        let err: Vec<u8> = VendingMachineError::AdminExpired(AdminExpired {
+            lastSeen: U256::from(1_200),
+        })
+        .into();
+        assert_eq!(contract.pause(), Err(err.clone()));
+        assert_eq!(contract.set_dead_man_timeout(U256::ZERO), Err(err.clone()));
+        assert_eq!(contract.set_grant_amount(U256::from(5)), Err(err.clone()));
+
+        // A pause left in place can still be lifted, without reviving the rest
+        let owner = contract.owner();
+        vm.set_sender(address!("0x1111111111111111111111111111111111111111"));
+        assert!(contract.unpause().is_err());
+        vm.set_sender(owner);
+        contract.unpause().unwrap();
+        assert!(!contract.paused());
+        assert_eq!(contract.withdraw_proceeds(owner), Ok(U256::ZERO));
+        assert!(!contract.is_admin_active());
+        assert_eq!(contract.set_grant_amount(U256::from(5)), Err(err));
+
+        // Users are unaffected
         let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
-        assert_eq!(contract.get_cupcake_balance_for(user).unwrap(), U256::ZERO);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+    }
 
-        vm.set_block_timestamp(vm.block_timestamp() + 6);
+    #[test]
+    fn test_scheduled_claim() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let keeper = address!("0x1111111111111111111111111111111111111111");
 
-        // Give a cupcake and verify it succeeds
-        assert!(contract.give_cupcake_to(user).unwrap());
+        vm.set_sender(keeper);
+        let err: Vec<u8> = VendingMachineError::NoScheduledClaim(NoScheduledClaim { user }).into();
+        assert_eq!(contract.execute_scheduled(user), Err(err.clone()));
 
-        // Check balance is now 1
+        vm.set_sender(user);
+        vm.set_block_timestamp(100);
+        contract.schedule_claim(U256::from(200)).unwrap();
+        assert_eq!(contract.scheduled_claim(user), U256::from(200));
+
+        vm.set_sender(keeper);
+        vm.set_block_timestamp(199);
+        let early: Vec<u8> = VendingMachineError::ScheduledClaimTooEarly(ScheduledClaimTooEarly {
+            user,
+            eligibleAt: U256::from(200),
+        })
+        .into();
+        assert_eq!(contract.execute_scheduled(user), Err(early));
+
+        vm.set_block_timestamp(200);
+        assert!(contract.execute_scheduled(user).unwrap());
         assert_eq!(
-            contract.get_cupcake_balance_for(user).unwrap(),
+            contract.get_default_cupcake_balance_for(user).unwrap(),
             U256::from(1)
         );
+        assert_eq!(contract.execute_scheduled(user), Err(err));
 
-        // Try to give another cupcake immediately - should fail due to time restriction
-        assert!(!contract.give_cupcake_to(user).unwrap());
+        // The cooldown still applies once the scheduled time has come
+        vm.set_sender(user);
+        contract.schedule_claim(U256::from(201)).unwrap();
+        vm.set_block_timestamp(201);
+        assert!(!contract.execute_scheduled(user).unwrap());
+        assert_eq!(contract.scheduled_claim(user), U256::from(201));
+        vm.set_block_timestamp(200 + COOLDOWN_SECONDS);
+        assert!(contract.execute_scheduled(user).unwrap());
+        assert_eq!(contract.scheduled_claim(user), U256::ZERO);
+    }
 
-        // Balance should still be 1
+    #[test]
+    fn test_get_limits() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let limits = contract.get_limits();
+        assert_eq!(limits.maxSupply, U256::ZERO);
+        assert_eq!(limits.maxBatchSize, U256::ZERO);
+
+        contract.set_max_supply(U256::from(1_000)).unwrap();
+        contract.set_daily_limit(U256::from(3)).unwrap();
+        contract.set_lifetime_cap(U256::from(50)).unwrap();
+        contract
+            .set_circuit_breaker(U256::from(20), U256::from(60))
+            .unwrap();
+        contract.set_max_claims_per_block(U256::from(7)).unwrap();
+        contract.set_max_batch_size(U256::from(100)).unwrap();
+
+        let limits = contract.get_limits();
+        assert_eq!(limits.maxSupply, U256::from(1_000));
+        assert_eq!(limits.dailyLimit, U256::from(3));
+        assert_eq!(limits.lifetimeCap, U256::from(50));
+        assert_eq!(limits.rateLimit, U256::from(20));
+        assert_eq!(limits.rateWindow, U256::from(60));
+        assert_eq!(limits.maxClaimsPerBlock, U256::from(7));
+        assert_eq!(limits.maxBatchSize, U256::from(100));
+    }
+
+    #[test]
+    fn test_unit_label_and_precision() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        assert_eq!(contract.unit_label(), "");
+        assert_eq!(contract.precision(), 0);
+
+        contract.set_unit_label("loyalty points".into()).unwrap();
+        assert_eq!(contract.unit_label(), "loyalty points");
+        let err: Vec<u8> = VendingMachineError::UnitLabelTooLong(UnitLabelTooLong {
+            length: U256::from(33),
+            max: U256::from(MAX_UNIT_LABEL_LENGTH),
+        })
+        .into();
+        assert_eq!(contract.set_unit_label("x".repeat(33)), Err(err));
+
+        let err: Vec<u8> = VendingMachineError::InvalidPrecision(InvalidPrecision {
+            precision: 19,
+            max: MAX_PRECISION,
+        })
+        .into();
+        assert_eq!(contract.set_precision(19), Err(err));
+
+        // Two decimals: a grant of 3 points mints 300 base units, and bonuses scale too
+        contract.set_precision(2).unwrap();
+        contract.set_grant_amount(U256::from(3)).unwrap();
+        contract.set_first_claim_bonus(U256::from(5)).unwrap();
+        contract
+            .set_streak_config(U256::ZERO, U256::from(1), U256::from(4))
+            .unwrap();
+        assert_eq!(contract.precision(), 2);
+        assert_eq!(contract.current_grant_amount(), U256::from(300));
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
         assert_eq!(
-            contract.get_cupcake_balance_for(user).unwrap(),
-            U256::from(1)
+            contract.effective_grant_amount(user).unwrap(),
+            U256::from(800)
+        );
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(800)
+        );
+        vm.set_sender(user);
+        assert_eq!(contract.claim_streak_bonus().unwrap(), U256::from(400));
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(user).unwrap(),
+            U256::from(1_200)
         );
 
-        // Advance block timestamp by 6 seconds
-        vm.set_block_timestamp(vm.block_timestamp() + 6);
+        // Existing balances pin the precision
+        vm.set_sender(contract.owner());
+        let err: Vec<u8> = VendingMachineError::PrecisionLocked(PrecisionLocked {
+            totalSupply: U256::from(1_200),
+        })
+        .into();
+        assert_eq!(contract.set_precision(3), Err(err));
+        assert_eq!(contract.precision(), 2);
+    }
 
-        // Now giving a cupcake should succeed
-        assert!(contract.give_cupcake_to(user).unwrap());
+    #[test]
+    fn test_batch_status() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let carol = address!("0x3333333333333333333333333333333333333333");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        vm.set_block_timestamp(102);
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+        vm.set_block_timestamp(104);
 
-        // Balance should now be 2
+        let users = vec![alice, bob, carol];
+        let statuses = contract.batch_status(users.clone()).unwrap();
+        assert_eq!(
+            statuses,
+            vec![
+                (false, U256::from(COOLDOWN_SECONDS - 4)),
+                (false, U256::from(COOLDOWN_SECONDS - 2)),
+                (true, U256::ZERO),
+            ]
+        );
+        for (user, status) in users.into_iter().zip(statuses) {
+            assert_eq!(
+                status,
+                (
+                    contract.can_receive_cupcake(user).unwrap(),
+                    contract.time_until_next_cupcake(user).unwrap()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_freeze_config() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        contract.set_grant_amount(U256::from(2)).unwrap();
+        contract.propose_cooldown(U256::from(60)).unwrap();
+        assert!(!contract.is_config_frozen());
+
+        contract.freeze_config().unwrap();
+        assert!(contract.is_config_frozen());
+        let err: Vec<u8> = VendingMachineError::ConfigFrozen(ConfigFrozen {}).into();
+        assert_eq!(contract.set_grant_amount(U256::from(5)), Err(err.clone()));
+        assert_eq!(contract.set_max_supply(U256::from(5)), Err(err.clone()));
+        assert_eq!(contract.execute_cooldown(), Err(err.clone()));
+        assert_eq!(
+            contract.set_user_cooldowns(vec![user], U256::ZERO),
+            Err(err.clone())
+        );
+        assert_eq!(
+            contract.set_distribution_time(user, U256::ZERO),
+            Err(err.clone())
+        );
+        assert_eq!(contract.freeze_config(), Err(err));
+
+        // Pausing and moderation still work by default
+        contract.pause().unwrap();
+        contract.unpause().unwrap();
+        contract.set_blacklisted(user, false).unwrap();
+
+        // Claims carry on under the frozen settings
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(user).unwrap());
         assert_eq!(
-            contract.get_cupcake_balance_for(user).unwrap(),
+            contract.get_default_cupcake_balance_for(user).unwrap(),
             U256::from(2)
         );
     }
-    /// This test demonstrates advanced configuration and usage of the TestVM for
-    /// comprehensive smart contract testing.
-    ///
-    /// It covers:
-    /// - Creating and configuring a TestVM with custom parameters
-    /// - Setting blockchain state (timestamps, block numbers)
-    /// - Interacting with contract methods
-    /// - Taking and inspecting VM state snapshots
-    /// - Mocking external contract calls
-    /// - Testing time-dependent contract behavior
+
     #[test]
-    fn test_advanced_testvm_configuration() {
-        // SECTION 1: TestVM Setup and Configuration
-        // -----------------------------------------
+    fn test_freeze_config_locks_pause() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_freeze_locks_pause(true).unwrap();
+        contract.freeze_config().unwrap();
 
-        // Create a TestVM with custom configuration using the builder pattern
-        // This approach allows for fluent, readable test setup
-        let vm: TestVM = TestVMBuilder::new()
-            // Set the transaction sender address (msg.sender in Solidity)
-            .sender(address!("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"))
-            // Set the address where our contract is deployed
-            .contract_address(address!("0x5FbDB2315678afecb367f032d93F642f64180aa3"))
-            // Set the ETH value sent with the transaction (msg.value in Solidity)
-            .value(U256::from(1))
-            .build();
+        let err: Vec<u8> = VendingMachineError::ConfigFrozen(ConfigFrozen {}).into();
+        assert_eq!(contract.pause(), Err(err));
+        assert!(!contract.paused());
+        contract.unpause().unwrap();
+    }
 
-        // Configure additional blockchain state parameters directly on the VM instance
-        // This demonstrates how to set parameters after VM creation
-        vm.set_block_number(12345678);
+    #[test]
+    fn test_burned_totals() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        contract.set_grant_amount(U256::from(10)).unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
 
-        // Note: The chain ID is set to 42161 (Arbitrum One) by default in the TestVM
-        // We don't need to set it explicitly as it's already configured in the VM state
+        vm.set_sender(alice);
+        contract.eat_cupcake(U256::from(3)).unwrap();
+        contract.eat_cupcake(U256::from(4)).unwrap();
+        vm.set_sender(bob);
+        contract.eat_cupcake(U256::from(5)).unwrap();
 
-        // SECTION 2: Contract Initialization and User Setup
-        // ------------------------------------------------
+        assert_eq!(contract.burned_by(alice), U256::from(7));
+        assert_eq!(contract.burned_by(bob), U256::from(5));
+        assert_eq!(contract.total_burned_global(), U256::from(12));
+        assert_eq!(contract.lifetime_received(alice), U256::from(10));
+    }
 
-        // Initialize our VendingMachine contract with the configured VM
-        // The `from` method connects our contract to the test environment
+    #[test]
+    fn test_buy_cupcake_payment_modes() {
+        let vm = TestVM::default();
         let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let buyer = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let treasury = address!("0x1111111111111111111111111111111111111111");
+        let price = U256::from(100);
 
-        // Define a user address that will interact with our contract
-        // This represents an external user's Ethereum address
-        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        vm.set_block_timestamp(100);
+        vm.set_sender(buyer);
+        let err: Vec<u8> = VendingMachineError::NotForSale(NotForSale {}).into();
+        assert_eq!(contract.buy_cupcake(), Err(err));
 
-        // SECTION 3: Initial State Verification
-        // ------------------------------------
+        vm.set_sender(owner);
+        contract.set_cupcake_price(price).unwrap();
 
-        // Verify the user starts with zero cupcakes
-        // This confirms our contract's initial state is as expected
-        assert_eq!(contract.get_cupcake_balance_for(user).unwrap(), U256::ZERO);
+        // Lenient by default: an underpayment comes straight back. TestVM doesn't credit
+        // msg_value to the contract, so its balance is set by hand.
+        vm.set_sender(buyer);
+        vm.set_value(U256::from(60));
+        vm.set_balance(vm.contract_address(), U256::from(60));
+        assert!(!contract.buy_cupcake().unwrap());
+        assert_eq!(vm.balance(buyer), U256::from(60));
+        assert_eq!(vm.balance(vm.contract_address()), U256::ZERO);
+        assert_eq!(contract.my_balance().unwrap(), U256::ZERO);
+        assert_eq!(contract.sale_proceeds(), U256::ZERO);
 
-        // Set the initial block timestamp by advancing it by 10 seconds
-        // This ensures we're past any time-based restrictions
-        vm.set_block_timestamp(vm.block_timestamp() + 10);
+        // Strict mode reverts instead
+        vm.set_sender(owner);
+        contract.set_strict_payment(true).unwrap();
+        vm.set_sender(buyer);
+        vm.set_balance(vm.contract_address(), U256::from(60));
+        let err: Vec<u8> = VendingMachineError::Underpaid(Underpaid {
+            sent: U256::from(60),
+            price,
+        })
+        .into();
+        assert_eq!(contract.buy_cupcake(), Err(err));
+        assert_eq!(vm.balance(buyer), U256::from(60));
 
-        // SECTION 4: Contract Interaction
-        // ------------------------------
+        // Overpaying keeps the price and refunds the change
+        vm.set_value(U256::from(130));
+        vm.set_balance(vm.contract_address(), U256::from(130));
+        assert!(contract.buy_cupcake().unwrap());
+        assert_eq!(contract.my_balance().unwrap(), U256::from(1));
+        assert_eq!(vm.balance(buyer), U256::from(90));
+        assert_eq!(contract.sale_proceeds(), price);
+        vm.set_value(U256::ZERO);
 
-        // Give a cupcake to the user and verify the operation succeeds
-        // The contract should return true when a cupcake is successfully given
-        assert!(contract.give_cupcake_to(user).unwrap());
+        vm.set_sender(owner);
+        assert_eq!(contract.withdraw_proceeds(treasury).unwrap(), price);
+        assert_eq!(vm.balance(treasury), price);
+        assert_eq!(contract.sale_proceeds(), U256::ZERO);
+    }
 
-        // Verify the user now has exactly one cupcake
-        // This confirms our contract correctly updated its storage
+    #[test]
+    fn test_buy_cupcake_follows_claim_rules() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let buyer = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+        let price = U256::from(100);
+        contract.set_cupcake_price(price).unwrap();
+        contract.set_lifetime_cap(U256::from(1)).unwrap();
+
+        vm.set_block_timestamp(100);
+        vm.set_sender(buyer);
+        vm.set_value(price);
+        vm.set_balance(vm.contract_address(), price);
+        assert!(contract.buy_cupcake().unwrap());
+        assert_eq!(contract.lifetime_received(buyer), U256::from(1));
+        assert_eq!(contract.sale_proceeds(), price);
+
+        // Paying doesn't skip the cooldown; the payment comes back
+        vm.set_balance(vm.contract_address(), price);
+        assert!(!contract.buy_cupcake().unwrap());
+        assert_eq!(vm.balance(buyer), price);
+        assert_eq!(contract.sale_proceeds(), price);
+
+        // Nor the lifetime cap
+        vm.set_block_timestamp(200);
+        let err: Vec<u8> = VendingMachineError::LifetimeCapReached(LifetimeCapReached {
+            user: buyer,
+            cap: U256::from(1),
+        })
+        .into();
+        assert_eq!(contract.buy_cupcake(), Err(err));
+        assert_eq!(contract.my_balance().unwrap(), U256::from(1));
+    }
+
+    #[test]
+    fn test_safe_mint() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let wallet = address!("0x1111111111111111111111111111111111111111");
+        let receiver = address!("0x2222222222222222222222222222222222222222");
+        let unaware = address!("0x3333333333333333333333333333333333333333");
+        vm.set_code(receiver, vec![0x60, 0x00]);
+        vm.set_code(unaware, vec![0x60, 0x00]);
+        contract.set_safe_mint(true).unwrap();
+
+        let calldata = IERC721Receiver::onERC721ReceivedCall {
+            operator: owner,
+            from: Address::ZERO,
+            tokenId: DEFAULT_FLAVOR,
+            data: Vec::new().into(),
+        }
+        .abi_encode();
+        let magic = FixedBytes::<4>::from(IERC721Receiver::onERC721ReceivedCall::SELECTOR);
+        vm.mock_call(
+            receiver,
+            calldata.clone(),
+            Ok(IERC721Receiver::onERC721ReceivedCall::abi_encode_returns(
+                &(magic,),
+            )),
+        );
+        vm.mock_call(
+            unaware,
+            calldata,
+            Ok(IERC721Receiver::onERC721ReceivedCall::abi_encode_returns(
+                &(FixedBytes::<4>::ZERO,),
+            )),
+        );
+
+        // Wallets need no hook, and an accepting contract gets its cupcake
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(wallet).unwrap());
+        assert!(contract.give_default_cupcake_to(receiver).unwrap());
         assert_eq!(
-            contract.get_cupcake_balance_for(user).unwrap(),
+            contract.get_default_cupcake_balance_for(receiver).unwrap(),
             U256::from(1)
         );
 
-        // SECTION 5: VM State Inspection
-        // -----------------------------
+        let err: Vec<u8> =
+            VendingMachineError::UnsafeRecipient(UnsafeRecipient { to: unaware }).into();
+        assert_eq!(contract.give_default_cupcake_to(unaware), Err(err));
+        assert_eq!(
+            contract.get_default_cupcake_balance_for(unaware).unwrap(),
+            U256::ZERO
+        );
+    }
 
-        // Take a snapshot of the current VM state for inspection
-        // This captures all storage, balances, and blockchain parameters
-        let snapshot = vm.snapshot();
+    #[test]
+    fn test_ownership_share_bps() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        let carol = address!("0x3333333333333333333333333333333333333333");
+        assert_eq!(contract.ownership_share_bps(alice).unwrap(), U256::ZERO);
 
-        // Inspect various aspects of the VM state to verify configuration
-        // Chain ID should be Arbitrum One (42161) which is the default
-        assert_eq!(snapshot.chain_id, 42161);
-        // Message value should match what we configured (1 wei)
-        assert_eq!(snapshot.msg_value, U256::from(1));
+        vm.set_block_timestamp(100);
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        assert_eq!(
+            contract.ownership_share_bps(alice).unwrap(),
+            U256::from(10_000)
+        );
 
-        // SECTION 6: Mocking External Contract Calls
-        // -----------------------------------------
+        // 1 of 3 is 3333.33... bps, rounded down
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+        assert!(contract.give_default_cupcake_to(carol).unwrap());
+        assert_eq!(contract.total_supply(), U256::from(3));
+        assert_eq!(
+            contract.ownership_share_bps(alice).unwrap(),
+            U256::from(3_333)
+        );
 
-        // Define an external contract we might want to interact with
-        let external_contract = address!("0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199");
-        // Define example call data we would send to that contract
-        let call_data = vec![0xab, 0xcd, 0xef];
-        // Define the expected response from that contract
-        let expected_response = vec![0x12, 0x34, 0x56];
+        // 2 of 3 is 6666.66... bps, also rounded down
+        vm.set_sender(carol);
+        assert!(contract.transfer_cupcake(bob, U256::from(1)).unwrap());
+        assert_eq!(
+            contract.ownership_share_bps(bob).unwrap(),
+            U256::from(6_666)
+        );
+        assert_eq!(contract.ownership_share_bps(carol).unwrap(), U256::ZERO);
+    }
 
-        // Mock the external call so it returns our expected response
-        // This allows testing contract interactions without deploying external contracts
-        vm.mock_call(external_contract, call_data, Ok(expected_response));
+    #[test]
+    fn test_balance_changed_events() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        contract.init().unwrap();
+        let owner = contract.owner();
+        let alice = address!("0x1111111111111111111111111111111111111111");
+        let bob = address!("0x2222222222222222222222222222222222222222");
+        contract.set_grant_amount(U256::from(10)).unwrap();
+        let changes_since = |seen: usize| -> Vec<(Address, u64, u64, u8)> {
+            vm.get_emitted_logs()[seen..]
+                .iter()
+                .filter(|(topics, _)| topics[0] == BalanceChanged::SIGNATURE_HASH)
+                .map(|(topics, data)| {
+                    let event =
+                        BalanceChanged::decode_raw_log(topics.iter().copied(), data, true).unwrap();
+                    (
+                        event.user,
+                        event.oldBalance.to(),
+                        event.newBalance.to(),
+                        event.reason,
+                    )
+                })
+                .collect()
+        };
 
-        // SECTION 7: Time-Dependent Behavior Testing
-        // -----------------------------------------
+        // Off by default
+        vm.set_block_timestamp(100);
+        let seen = vm.get_emitted_logs().len();
+        assert!(contract.give_default_cupcake_to(bob).unwrap());
+        assert!(changes_since(seen).is_empty());
 
-        // Set a specific block timestamp
-        // This simulates the passage of time on the blockchain
-        vm.set_block_timestamp(1006);
+        contract.set_balance_events(true).unwrap();
+        let seen = vm.get_emitted_logs().len();
+        assert!(contract.give_default_cupcake_to(alice).unwrap());
+        assert_eq!(
+            changes_since(seen),
+            vec![(alice, 0, 10, BALANCE_REASON_GRANT)]
+        );
 
-        // Try giving another cupcake after the time restriction has passed
-        // The contract should allow this since enough time has elapsed
-        assert!(contract.give_cupcake_to(user).unwrap());
+        let seen = vm.get_emitted_logs().len();
+        vm.set_sender(alice);
+        assert!(contract.transfer_cupcake(bob, U256::from(4)).unwrap());
+        assert_eq!(
+            changes_since(seen),
+            vec![
+                (alice, 10, 6, BALANCE_REASON_TRANSFER_OUT),
+                (bob, 10, 14, BALANCE_REASON_TRANSFER_IN),
+            ]
+        );
 
-        // Verify the user now has two cupcakes
-        // This confirms our contract correctly handles time-based restrictions
+        let seen = vm.get_emitted_logs().len();
+        contract.eat_cupcake(U256::from(1)).unwrap();
         assert_eq!(
-            contract.get_cupcake_balance_for(user).unwrap(),
-            U256::from(2)
+            changes_since(seen),
+            vec![(alice, 6, 5, BALANCE_REASON_BURN)]
+        );
+
+        vm.set_sender(owner);
+        let seen = vm.get_emitted_logs().len();
+        contract
+            .adjust_balance(alice, I256::try_from(-2).unwrap())
+            .unwrap();
+        contract
+            .adjust_balance(bob, I256::try_from(3).unwrap())
+            .unwrap();
+        assert_eq!(
+            changes_since(seen),
+            vec![
+                (alice, 5, 3, BALANCE_REASON_ADMIN),
+                (bob, 14, 17, BALANCE_REASON_ADMIN),
+            ]
+        );
+
+        // Decay shows up once it's written to storage
+        contract.set_decay(true, U256::from(1)).unwrap();
+        vm.set_block_timestamp(102);
+        let seen = vm.get_emitted_logs().len();
+        vm.set_sender(alice);
+        contract.eat_cupcake(U256::from(1)).unwrap();
+        assert_eq!(
+            changes_since(seen),
+            vec![
+                (alice, 3, 1, BALANCE_REASON_DECAY),
+                (alice, 1, 0, BALANCE_REASON_BURN),
+            ]
+        );
+
+        // Other flavors are reported too, tagged with their id
+        let sprinkles = U256::from(7);
+        let seen = vm.get_emitted_logs().len();
+        assert!(contract.give_cupcake_to(bob, sprinkles).unwrap());
+        let events: Vec<_> = vm.get_emitted_logs()[seen..]
+            .iter()
+            .filter(|(topics, _)| topics[0] == BalanceChanged::SIGNATURE_HASH)
+            .map(|(topics, data)| {
+                BalanceChanged::decode_raw_log(topics.iter().copied(), data, true).unwrap()
+            })
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].user, bob);
+        assert_eq!(events[0].flavorId, sprinkles);
+        assert_eq!(events[0].oldBalance, U256::ZERO);
+        assert_eq!(events[0].newBalance, U256::from(10));
+        assert_eq!(events[0].reason, BALANCE_REASON_GRANT);
+    }
+}
+
+#[cfg(all(test, feature = "no-admin"))]
+mod no_admin_test {
+    use super::*;
+    use alloy_primitives::{address, I256};
+    use stylus_sdk::abi::Router;
+    use stylus_sdk::function_selector;
+    use stylus_sdk::testing::*;
+
+    fn routes(contract: &mut VendingMachine, selector: [u8; 4]) -> bool {
+        let selector = u32::from_be_bytes(selector);
+        <VendingMachine as Router<VendingMachine>>::route(contract, selector, &[]).is_some()
+    }
+
+    #[test]
+    fn test_admin_surface_is_absent() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+
+        assert!(!routes(&mut contract, function_selector!("init")));
+        assert!(!routes(&mut contract, function_selector!("owner")));
+        assert!(!routes(&mut contract, function_selector!("pause")));
+        assert!(!routes(
+            &mut contract,
+            function_selector!("setBlacklisted", Address, bool)
+        ));
+        assert!(!routes(
+            &mut contract,
+            function_selector!("adjustBalance", Address, I256)
+        ));
+        assert!(routes(&mut contract, function_selector!("paused")));
+    }
+
+    #[test]
+    fn test_core_still_works() {
+        let vm = TestVM::default();
+        let mut contract = VendingMachine::from(&vm);
+        let user = address!("0xCDC41bff86a62716f050622325CC17a317f99404");
+
+        vm.set_block_timestamp(100);
+        assert!(contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert!(!contract.give_cupcake_to(user, DEFAULT_FLAVOR).unwrap());
+        assert_eq!(
+            contract
+                .get_cupcake_balance_for(user, DEFAULT_FLAVOR)
+                .unwrap(),
+            U256::from(1)
         );
     }
 }